@@ -8,25 +8,96 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-/// A very simple BitVector type.
-pub struct BitVector {
-    data: Vec<u64>,
+use std::cmp;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops;
+
+/// The word types that can back a `BitSet`. Implemented for `u32`
+/// and `u64`; the smaller words waste less space in small sets.
+pub trait Word:
+    Copy
+    + Eq
+    + Hash
+    + fmt::Debug
+    + ops::BitOr<Output = Self>
+    + ops::BitAnd<Output = Self>
+    + ops::Not<Output = Self>
+    + ops::BitOrAssign
+    + ops::BitAndAssign
+    + ops::ShrAssign<usize>
+{
+    /// The number of bits in a word.
+    const BITS: usize;
+
+    /// The word with no bits set.
+    const ZERO: Self;
+
+    /// The word with only bit `index` set.
+    fn bit(index: usize) -> Self;
+
+    fn count_ones(self) -> u32;
+
+    fn trailing_zeros(self) -> u32;
+
+    fn leading_zeros(self) -> u32;
+}
+
+macro_rules! impl_word {
+    ($($t:ty)*) => {
+        $(
+            impl Word for $t {
+                const BITS: usize = <$t>::BITS as usize;
+                const ZERO: Self = 0;
+
+                fn bit(index: usize) -> Self {
+                    1 << index
+                }
+
+                fn count_ones(self) -> u32 {
+                    <$t>::count_ones(self)
+                }
+
+                fn trailing_zeros(self) -> u32 {
+                    <$t>::trailing_zeros(self)
+                }
+
+                fn leading_zeros(self) -> u32 {
+                    <$t>::leading_zeros(self)
+                }
+            }
+        )*
+    }
+}
+
+impl_word!(u32 u64);
+
+/// A very simple bit set type, generic over its word type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitSet<W: Word> {
+    // The number of bits the vector was created with, or grown to;
+    // `data` has just enough words for them.
+    elements: usize,
+    data: Vec<W>,
 }
 
-impl BitVector {
-    pub fn new(num_bits: usize) -> BitVector {
-        let num_words = u64s(num_bits);
-        BitVector { data: vec![0; num_words] }
+/// The usual bit set, backed by `u64` words.
+pub type BitVector = BitSet<u64>;
+
+impl<W: Word> BitSet<W> {
+    pub fn new(num_bits: usize) -> BitSet<W> {
+        let num_words = words_for::<W>(num_bits);
+        BitSet { elements: num_bits, data: vec![W::ZERO; num_words] }
     }
 
     pub fn contains(&self, bit: usize) -> bool {
-        let (word, mask) = word_mask(bit);
-        (self.data[word] & mask) != 0
+        let (word, mask) = word_mask::<W>(bit);
+        (self.data[word] & mask) != W::ZERO
     }
 
     /// Returns true if the bit has changed.
     pub fn insert(&mut self, bit: usize) -> bool {
-        let (word, mask) = word_mask(bit);
+        let (word, mask) = word_mask::<W>(bit);
         let data = &mut self.data[word];
         let value = *data;
         let new_value = value | mask;
@@ -34,7 +105,24 @@ impl BitVector {
         new_value != value
     }
 
-    pub fn insert_all(&mut self, all: &BitVector) -> bool {
+    /// Like `insert`, but first grows the vector if `bit` is past its
+    /// end, so that the universe can be discovered as bits are added.
+    /// Returns true if the bit has changed.
+    pub fn insert_and_track(&mut self, bit: usize) -> bool {
+        self.grow(bit + 1);
+        self.insert(bit)
+    }
+
+    /// Returns the highest set bit, if any. Scans from the last word
+    /// down, skipping over empty words.
+    pub fn max_set_bit(&self) -> Option<usize> {
+        self.data
+            .iter()
+            .rposition(|&word| word != W::ZERO)
+            .map(|n| n * W::BITS + W::BITS - 1 - self.data[n].leading_zeros() as usize)
+    }
+
+    pub fn insert_all(&mut self, all: &BitSet<W>) -> bool {
         assert!(self.data.len() == all.data.len());
         let mut changed = false;
         for (i, j) in self.data.iter_mut().zip(&all.data) {
@@ -47,38 +135,199 @@ impl BitVector {
         changed
     }
 
+    /// Returns a new vector with the bits that are set in either
+    /// `self` or `other`. The result is as long as the longer of the
+    /// two.
+    pub fn union(&self, other: &BitSet<W>) -> BitSet<W> {
+        let (longer, shorter) = if self.data.len() >= other.data.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut result = longer.clone();
+        result.elements = cmp::max(self.elements, other.elements);
+        for (i, j) in result.data.iter_mut().zip(&shorter.data) {
+            *i |= *j;
+        }
+        result
+    }
+
+    /// Returns a new vector with the bits that are set in both `self`
+    /// and `other`. The result is as long as the shorter of the two.
+    pub fn intersection(&self, other: &BitSet<W>) -> BitSet<W> {
+        BitSet {
+            elements: cmp::min(self.elements, other.elements),
+            data: self.data.iter().zip(&other.data).map(|(&i, &j)| i & j).collect(),
+        }
+    }
+
+    /// Returns a new vector with the bits that are set in `self` but
+    /// not in `other`. The result is as long as `self`.
+    pub fn difference(&self, other: &BitSet<W>) -> BitSet<W> {
+        let mut result = self.clone();
+        for (i, j) in result.data.iter_mut().zip(&other.data) {
+            *i &= !*j;
+        }
+        result
+    }
+
+    /// Ensures that the vector has room for at least `num_bits` bits;
+    /// the new bits are unset. Never shrinks the vector.
     pub fn grow(&mut self, num_bits: usize) {
-        let num_words = u64s(num_bits);
-        let extra_words = self.data.len() - num_words;
-        self.data.extend((0..extra_words).map(|_| 0));
+        self.elements = cmp::max(self.elements, num_bits);
+        let num_words = words_for::<W>(num_bits);
+        if num_words > self.data.len() {
+            self.data.resize(num_words, W::ZERO);
+        }
+    }
+
+    /// Sets every bit that is set in `other`, first growing `self` to
+    /// the length of `other` if it is shorter. Returns true if any
+    /// bit has changed.
+    pub fn union_with(&mut self, other: &BitSet<W>) -> bool {
+        self.grow(other.elements);
+        let mut changed = false;
+        for (i, j) in self.data.iter_mut().zip(&other.data) {
+            let value = *i | *j;
+            changed |= value != *i;
+            *i = value;
+        }
+        changed
+    }
+
+    /// Clears every bit that is not set in `other`. If `other` is
+    /// shorter, all of `self`'s bits beyond its length are cleared;
+    /// `self` keeps its length either way. Returns true if any bit
+    /// has changed.
+    pub fn intersect_with(&mut self, other: &BitSet<W>) -> bool {
+        let mut changed = false;
+        for (n, i) in self.data.iter_mut().enumerate() {
+            let value = *i & other.data.get(n).cloned().unwrap_or(W::ZERO);
+            changed |= value != *i;
+            *i = value;
+        }
+        changed
+    }
+
+    /// Clears every bit that is set in `other`. Any part of `other`
+    /// beyond the length of `self` is ignored, and `self` keeps its
+    /// length. Returns true if any bit has changed.
+    pub fn subtract(&mut self, other: &BitSet<W>) -> bool {
+        let mut changed = false;
+        for (i, j) in self.data.iter_mut().zip(&other.data) {
+            let value = *i & !*j;
+            changed |= value != *i;
+            *i = value;
+        }
+        changed
+    }
+
+    /// Returns the number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.data.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// The words backing this vector; bit `i` is stored in word
+    /// `i / W::BITS`, at position `i % W::BITS`. This is an escape
+    /// hatch for implementing operations that are not otherwise
+    /// provided.
+    ///
+    /// The last word may have room for bits past the number of bits
+    /// the vector was created with; callers that combine words
+    /// themselves are responsible for masking those out.
+    pub fn words(&self) -> &[W] {
+        &self.data
+    }
+
+    /// The number of words in `words()`.
+    pub fn num_words(&self) -> usize {
+        self.data.len()
     }
 
     /// Iterates over indexes of set bits in a sorted order
-    pub fn iter<'a>(&'a self) -> BitVectorIter<'a> {
-        BitVectorIter {
+    pub fn iter(&self) -> BitSetIter<'_, W> {
+        BitSetIter {
             iter: self.data.iter(),
-            current: 0,
+            current: W::ZERO,
             idx: 0,
         }
     }
+
+    /// Iterates, in sorted order, over the indexes of bits set in both
+    /// `self` and `other`, without allocating. Words that only one of
+    /// the vectors has are treated as zero.
+    pub fn iter_intersection<'a>(&'a self, other: &'a BitSet<W>) -> impl Iterator<Item = usize> + 'a {
+        set_bits(self.data.iter().zip(&other.data).map(|(&i, &j)| i & j))
+    }
+
+    /// Iterates, in sorted order, over the indexes of bits set in
+    /// `self` but not in `other`, without allocating. Words past the
+    /// end of `other` are treated as zero.
+    pub fn iter_difference<'a>(&'a self, other: &'a BitSet<W>) -> impl Iterator<Item = usize> + 'a {
+        set_bits(
+            self.data
+                .iter()
+                .enumerate()
+                .map(move |(n, &i)| i & !other.data.get(n).cloned().unwrap_or(W::ZERO)),
+        )
+    }
+}
+
+/// The indexes of the bits set in a sequence of words.
+fn set_bits<W: Word, I: Iterator<Item = W>>(words: I) -> impl Iterator<Item = usize> {
+    words.enumerate().flat_map(|(n, mut word)| {
+        let base = n * W::BITS;
+        ::std::iter::from_fn(move || {
+            if word == W::ZERO {
+                return None;
+            }
+            let offset = word.trailing_zeros() as usize;
+            word &= !W::bit(offset);
+            Some(base + offset)
+        })
+    })
+}
+
+impl<W: Word> ops::BitOr<&BitSet<W>> for &BitSet<W> {
+    type Output = BitSet<W>;
+    fn bitor(self, other: &BitSet<W>) -> BitSet<W> {
+        self.union(other)
+    }
+}
+
+impl<W: Word> ops::BitAnd<&BitSet<W>> for &BitSet<W> {
+    type Output = BitSet<W>;
+    fn bitand(self, other: &BitSet<W>) -> BitSet<W> {
+        self.intersection(other)
+    }
+}
+
+impl<W: Word> ops::Sub<&BitSet<W>> for &BitSet<W> {
+    type Output = BitSet<W>;
+    fn sub(self, other: &BitSet<W>) -> BitSet<W> {
+        self.difference(other)
+    }
 }
 
-pub struct BitVectorIter<'a> {
-    iter: ::std::slice::Iter<'a, u64>,
-    current: u64,
+pub struct BitSetIter<'a, W: Word + 'a> {
+    iter: ::std::slice::Iter<'a, W>,
+    current: W,
     idx: usize,
 }
 
-impl<'a> Iterator for BitVectorIter<'a> {
+/// The iterator over a `BitVector`.
+pub type BitVectorIter<'a> = BitSetIter<'a, u64>;
+
+impl<'a, W: Word> Iterator for BitSetIter<'a, W> {
     type Item = usize;
     fn next(&mut self) -> Option<usize> {
-        while self.current == 0 {
+        while self.current == W::ZERO {
             self.current = if let Some(&i) = self.iter.next() {
-                if i == 0 {
-                    self.idx += 64;
+                if i == W::ZERO {
+                    self.idx += W::BITS;
                     continue;
                 } else {
-                    self.idx = u64s(self.idx) * 64;
+                    self.idx = words_for::<W>(self.idx) * W::BITS;
                     i
                 }
             } else {
@@ -89,7 +338,7 @@ impl<'a> Iterator for BitVectorIter<'a> {
         self.current >>= offset;
         self.current >>= 1; // shift otherwise overflows for 0b1000_0000_…_0000
         self.idx += offset + 1;
-        return Some(self.idx - 1);
+        Some(self.idx - 1)
     }
 }
 
@@ -109,7 +358,7 @@ impl BitMatrix {
         // element. Round up to an even number of u64s.
         let u64s_per_elem = u64s(elements);
         BitMatrix {
-            elements: elements,
+            elements,
             vector: vec![0; elements * u64s_per_elem],
         }
     }
@@ -121,10 +370,25 @@ impl BitMatrix {
         (start, start + u64s_per_elem)
     }
 
+    /// The words for a given element, with any bits past the last
+    /// column masked out.
+    fn masked_row(&self, element: usize) -> impl Iterator<Item = u64> + '_ {
+        let (start, end) = self.range(element);
+        let last_mask = last_word_mask(self.elements);
+        self.vector[start..end]
+            .iter()
+            .enumerate()
+            .map(move |(i, &word)| if start + i + 1 == end { word & last_mask } else { word })
+    }
+
+    /// Sets the bit for `target` in the row of `source`, returning
+    /// true if it has changed. Panics if `source` or `target` is out
+    /// of range.
     pub fn add(&mut self, source: usize, target: usize) -> bool {
+        assert!(target < self.elements, "column {} is out of range", target);
         let (start, _) = self.range(source);
-        let (word, mask) = word_mask(target);
-        let mut vector = &mut self.vector[..];
+        let (word, mask) = word_mask::<u64>(target);
+        let vector = &mut self.vector[..];
         let v1 = vector[start + word];
         let v2 = v1 | mask;
         vector[start + word] = v2;
@@ -134,13 +398,23 @@ impl BitMatrix {
     /// Do the bits from `source` contain `target`?
     ///
     /// Put another way, if the matrix represents (transitive)
-    /// reachability, can `source` reach `target`?
+    /// reachability, can `source` reach `target`? This is a single
+    /// word lookup. Like `add`, it panics if `source` or `target` is
+    /// out of range.
     pub fn contains(&self, source: usize, target: usize) -> bool {
+        assert!(target < self.elements, "column {} is out of range", target);
         let (start, _) = self.range(source);
-        let (word, mask) = word_mask(target);
+        let (word, mask) = word_mask::<u64>(target);
         (self.vector[start + word] & mask) != 0
     }
 
+    /// Returns the number of bits set for `source`, i.e., how many
+    /// elements it can reach. Bits past the last column are not
+    /// counted.
+    pub fn row_count_ones(&self, source: usize) -> usize {
+        self.masked_row(source).map(|word| word.count_ones() as usize).sum()
+    }
+
     /// Returns those indices that are reachable from both `a` and
     /// `b`. This is an O(n) operation where `n` is the number of
     /// elements (somewhat independent from the actual size of the
@@ -180,19 +454,159 @@ impl BitMatrix {
             let v1 = vector[write_index];
             let v2 = v1 | vector[read_index];
             vector[write_index] = v2;
-            changed = changed | (v1 != v2);
+            changed |= v1 != v2;
         }
         changed
     }
+
+    /// Sets every bit that is set in `other`, return true if anything
+    /// changed. Panics if `other` does not have the same number of
+    /// elements.
+    pub fn union_with(&mut self, other: &BitMatrix) -> bool {
+        assert_eq!(
+            self.elements,
+            other.elements,
+            "cannot combine matrices with different numbers of elements"
+        );
+        let mut changed = false;
+        for (word, &other_word) in self.vector.iter_mut().zip(&other.vector) {
+            let v1 = *word;
+            let v2 = v1 | other_word;
+            *word = v2;
+            changed |= v1 != v2;
+        }
+        changed
+    }
+
+    /// Clears every bit that is not set in `other`, return true if
+    /// anything changed. Panics if `other` does not have the same
+    /// number of elements.
+    pub fn intersect_with(&mut self, other: &BitMatrix) -> bool {
+        assert_eq!(
+            self.elements,
+            other.elements,
+            "cannot combine matrices with different numbers of elements"
+        );
+        let mut changed = false;
+        for (word, &other_word) in self.vector.iter_mut().zip(&other.vector) {
+            let v1 = *word;
+            let v2 = v1 & other_word;
+            *word = v2;
+            changed |= v1 != v2;
+        }
+        changed
+    }
+
+    /// Clear the bits of `row` that are not present in `mask`,
+    /// return true if anything changed.
+    ///
+    /// `mask` must have as many bits as the matrix has columns.
+    pub fn intersect_row_with(&mut self, row: usize, mask: &BitVector) -> bool {
+        let (start, end) = self.range(row);
+        self.assert_row_mask(mask);
+        let mut changed = false;
+        for (word, &mask_word) in self.vector[start..end].iter_mut().zip(&mask.data) {
+            let v1 = *word;
+            let v2 = v1 & mask_word;
+            *word = v2;
+            changed |= v1 != v2;
+        }
+        changed
+    }
+
+    /// Sets bit `(i, i)` for every element `i`, i.e., adds the identity
+    /// relation; this is how to make a reachability matrix reflexive.
+    pub fn set_diagonal(&mut self) {
+        for i in 0..self.elements {
+            self.add(i, i);
+        }
+    }
+
+    /// Clears bit `(i, i)` for every element `i`, i.e., removes the
+    /// identity relation.
+    pub fn clear_diagonal(&mut self) {
+        for i in 0..self.elements {
+            let (start, _) = self.range(i);
+            let (word, mask) = word_mask::<u64>(i);
+            self.vector[start + word] &= !mask;
+        }
+    }
+
+    /// Clears all the bits of `row`.
+    pub fn clear_row(&mut self, row: usize) {
+        let (start, end) = self.range(row);
+        for word in &mut self.vector[start..end] {
+            *word = 0;
+        }
+    }
+
+    /// Overwrites the bits of `row` with those of `mask`, unlike
+    /// `merge`, which only adds bits. Bits of `mask` past the last
+    /// column are not copied.
+    ///
+    /// As for `intersect_row_with`, `mask` must have as many bits as
+    /// the matrix has columns.
+    pub fn set_row(&mut self, row: usize, mask: &BitVector) {
+        let (start, end) = self.range(row);
+        self.assert_row_mask(mask);
+        self.vector[start..end].copy_from_slice(&mask.data);
+        if end > start {
+            self.vector[end - 1] &= last_word_mask(self.elements);
+        }
+    }
+
+    fn assert_row_mask(&self, mask: &BitVector) {
+        assert_eq!(
+            self.elements,
+            mask.elements,
+            "mask does not have as many bits as the matrix has columns"
+        );
+    }
+}
+
+/// Two matrices are equal if they have the same number of elements
+/// and the same bits set; bits past the last column (in the padding
+/// of a row's last word) are ignored.
+impl PartialEq for BitMatrix {
+    fn eq(&self, other: &BitMatrix) -> bool {
+        self.elements == other.elements
+            && (0..self.elements).all(|row| self.masked_row(row).eq(other.masked_row(row)))
+    }
+}
+
+impl Eq for BitMatrix {}
+
+impl Hash for BitMatrix {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.elements.hash(state);
+        for row in 0..self.elements {
+            for word in self.masked_row(row) {
+                word.hash(state);
+            }
+        }
+    }
 }
 
 fn u64s(elements: usize) -> usize {
-    (elements + 63) / 64
+    words_for::<u64>(elements)
 }
 
-fn word_mask(index: usize) -> (usize, u64) {
-    let word = index / 64;
-    let mask = 1 << (index % 64);
+fn words_for<W: Word>(elements: usize) -> usize {
+    elements.div_ceil(W::BITS)
+}
+
+/// The mask of the bits in use in the last word of a vector of
+/// `elements` bits.
+fn last_word_mask(elements: usize) -> u64 {
+    match elements % 64 {
+        0 => !0,
+        bits => (1 << bits) - 1,
+    }
+}
+
+fn word_mask<W: Word>(index: usize) -> (usize, W) {
+    let word = index / W::BITS;
+    let mask = W::bit(index % W::BITS);
     (word, mask)
 }
 
@@ -255,6 +669,37 @@ fn union_two_vecs() {
     assert!(vec1.contains(64));
 }
 
+#[test]
+fn set_operations() {
+    let mut vec1 = BitVector::new(65);
+    let mut vec2 = BitVector::new(130);
+    vec1.insert(3);
+    vec1.insert(64);
+    vec2.insert(5);
+    vec2.insert(64);
+    vec2.insert(129);
+
+    let union = &vec1 | &vec2;
+    assert_eq!(union, vec1.union(&vec2));
+    assert_eq!(union, vec2.union(&vec1));
+    assert_eq!(union.num_words(), 3);
+    assert_eq!(union.iter().collect::<Vec<_>>(), [3, 5, 64, 129]);
+
+    let intersection = &vec1 & &vec2;
+    assert_eq!(intersection.num_words(), 2);
+    assert_eq!(intersection.iter().collect::<Vec<_>>(), [64]);
+
+    let difference = &vec1 - &vec2;
+    assert_eq!(difference.num_words(), 2);
+    assert_eq!(difference.iter().collect::<Vec<_>>(), [3]);
+    let difference = &vec2 - &vec1;
+    assert_eq!(difference.num_words(), 3);
+    assert_eq!(difference.iter().collect::<Vec<_>>(), [5, 129]);
+
+    // The operands are untouched.
+    assert_eq!(vec1.iter().collect::<Vec<_>>(), [3, 64]);
+}
+
 #[test]
 fn grow() {
     let mut vec1 = BitVector::new(65);
@@ -269,6 +714,19 @@ fn grow() {
     assert!(!vec1.contains(126));
 }
 
+#[test]
+fn count_ones_and_words() {
+    let mut vec1 = BitVector::new(130);
+    assert_eq!(vec1.count_ones(), 0);
+    assert_eq!(vec1.num_words(), 3);
+    vec1.insert(0);
+    vec1.insert(63);
+    vec1.insert(64);
+    vec1.insert(129);
+    assert_eq!(vec1.count_ones(), 4);
+    assert_eq!(vec1.words(), &[1 | 1 << 63, 1, 2]);
+}
+
 #[test]
 fn matrix_intersection() {
     let mut vec1 = BitMatrix::new(200);
@@ -299,3 +757,344 @@ fn matrix_intersection() {
     let intersection = vec1.intersection(2, 65);
     assert_eq!(intersection, &[10, 64, 160]);
 }
+
+#[test]
+fn matrix_intersect_row_with() {
+    let mut matrix = BitMatrix::new(100);
+    matrix.add(3, 1);
+    matrix.add(3, 64);
+    matrix.add(3, 99);
+    matrix.add(4, 1);
+
+    let mut mask = BitVector::new(100);
+    mask.insert(1);
+    mask.insert(99);
+
+    assert!(matrix.intersect_row_with(3, &mask));
+    assert!(!matrix.intersect_row_with(3, &mask));
+    assert!(matrix.contains(3, 1));
+    assert!(!matrix.contains(3, 64));
+    assert!(matrix.contains(3, 99));
+    assert!(matrix.contains(4, 1));
+}
+
+#[test]
+#[should_panic(expected = "as many bits as the matrix has columns")]
+fn matrix_intersect_row_with_wrong_size() {
+    let mut matrix = BitMatrix::new(100);
+    matrix.intersect_row_with(0, &BitVector::new(200));
+}
+
+#[test]
+#[should_panic(expected = "as many bits as the matrix has columns")]
+fn matrix_intersect_row_with_same_words() {
+    // 65 and 100 bits both take two words, but only the bit length
+    // counts.
+    let mut matrix = BitMatrix::new(100);
+    matrix.intersect_row_with(0, &BitVector::new(65));
+}
+
+#[test]
+fn matrix_row_count_ones() {
+    let mut matrix = BitMatrix::new(70);
+    assert_eq!(matrix.row_count_ones(1), 0);
+    matrix.add(1, 0);
+    matrix.add(1, 63);
+    matrix.add(1, 64);
+    matrix.add(1, 69);
+    matrix.vector[3] |= 1 << (100 - 64); // padding, not a real column
+    matrix.add(2, 5);
+    assert_eq!(matrix.row_count_ones(1), 4);
+    assert_eq!(matrix.row_count_ones(2), 1);
+}
+
+#[test]
+#[should_panic(expected = "column 70 is out of range")]
+fn matrix_add_column_out_of_range() {
+    let mut matrix = BitMatrix::new(10);
+    matrix.add(0, 70);
+}
+
+#[test]
+#[should_panic(expected = "column 10 is out of range")]
+fn matrix_contains_column_out_of_range() {
+    let matrix = BitMatrix::new(10);
+    matrix.contains(0, 10);
+}
+
+#[test]
+fn matrix_eq_and_hash() {
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(matrix: &BitMatrix) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        matrix.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let mut matrix1 = BitMatrix::new(65);
+    matrix1.add(0, 3);
+    matrix1.add(64, 64);
+    let mut matrix2 = matrix1.clone();
+    assert!(matrix1 == matrix2);
+    assert_eq!(hash(&matrix1), hash(&matrix2));
+
+    // A padding bit past the last column does not count.
+    matrix2.vector[1] |= 1 << (70 - 64);
+    assert!(matrix1 == matrix2);
+    assert_eq!(hash(&matrix1), hash(&matrix2));
+
+    matrix2.add(1, 3);
+    assert!(matrix1 != matrix2);
+
+    assert!(BitMatrix::new(64) != BitMatrix::new(65));
+}
+
+#[test]
+fn grow_adds_words() {
+    let mut vec = BitVector::new(10);
+    vec.insert(3);
+    vec.grow(200);
+    assert_eq!(vec.num_words(), 4);
+    vec.insert(199);
+    vec.grow(10);
+    assert_eq!(vec.num_words(), 4);
+    assert_eq!(vec.iter().collect::<Vec<_>>(), [3, 199]);
+}
+
+#[test]
+fn union_with_mixed_lengths() {
+    let mut short = BitVector::new(64);
+    short.insert(1);
+    let mut long = BitVector::new(192);
+    long.insert(1);
+    long.insert(150);
+
+    let mut a = short.clone();
+    assert!(a.union_with(&long));
+    assert_eq!(a.num_words(), 3);
+    assert_eq!(a.iter().collect::<Vec<_>>(), [1, 150]);
+    assert!(!a.union_with(&long));
+
+    let mut b = long.clone();
+    short.insert(2);
+    assert!(b.union_with(&short));
+    assert_eq!(b.num_words(), 3);
+    assert_eq!(b.iter().collect::<Vec<_>>(), [1, 2, 150]);
+}
+
+#[test]
+fn intersect_with_mixed_lengths() {
+    let mut short = BitVector::new(64);
+    short.insert(1);
+    short.insert(2);
+    let mut long = BitVector::new(192);
+    long.insert(1);
+    long.insert(150);
+
+    let mut a = short.clone();
+    assert!(a.intersect_with(&long));
+    assert_eq!(a.num_words(), 1);
+    assert_eq!(a.iter().collect::<Vec<_>>(), [1]);
+
+    let mut b = long.clone();
+    assert!(b.intersect_with(&short));
+    assert_eq!(b.num_words(), 3);
+    assert_eq!(b.iter().collect::<Vec<_>>(), [1]);
+    assert!(!b.intersect_with(&short));
+}
+
+#[test]
+fn subtract_mixed_lengths() {
+    let mut short = BitVector::new(64);
+    short.insert(1);
+    short.insert(2);
+    let mut long = BitVector::new(192);
+    long.insert(1);
+    long.insert(150);
+
+    let mut a = short.clone();
+    assert!(a.subtract(&long));
+    assert_eq!(a.num_words(), 1);
+    assert_eq!(a.iter().collect::<Vec<_>>(), [2]);
+
+    let mut b = long.clone();
+    assert!(b.subtract(&short));
+    assert_eq!(b.num_words(), 3);
+    assert_eq!(b.iter().collect::<Vec<_>>(), [150]);
+    assert!(!b.subtract(&short));
+}
+
+#[test]
+fn clear_and_set_row() {
+    let mut matrix = BitMatrix::new(70);
+    matrix.add(3, 1);
+    matrix.add(3, 68);
+    matrix.add(4, 2);
+
+    let mut mask = BitVector::new(70);
+    mask.insert(5);
+    mask.insert(69);
+    mask.insert(100); // past the last column
+    matrix.set_row(3, &mask);
+    assert!(!matrix.contains(3, 1));
+    assert!(!matrix.contains(3, 68));
+    assert!(matrix.contains(3, 5));
+    assert!(matrix.contains(3, 69));
+    assert_eq!(matrix.vector[3 * 2 + 1] & (1 << (100 - 64)), 0);
+    assert!(matrix.contains(4, 2));
+
+    matrix.clear_row(3);
+    assert_eq!(matrix.row_count_ones(3), 0);
+    assert!(matrix.contains(4, 2));
+}
+
+#[test]
+#[should_panic(expected = "as many bits as the matrix has columns")]
+fn set_row_wrong_length() {
+    let mut matrix = BitMatrix::new(70);
+    matrix.set_row(0, &BitVector::new(10));
+}
+
+#[test]
+#[should_panic(expected = "as many bits as the matrix has columns")]
+fn set_row_same_words() {
+    let mut matrix = BitMatrix::new(100);
+    matrix.set_row(0, &BitVector::new(65));
+}
+
+#[test]
+#[should_panic]
+fn add_out_of_range() {
+    let mut matrix = BitMatrix::new(10);
+    matrix.add(10, 3);
+}
+
+#[test]
+#[should_panic]
+fn contains_out_of_range() {
+    let matrix = BitMatrix::new(10);
+    matrix.contains(10, 3);
+}
+
+#[test]
+fn u32_words() {
+    let mut set: BitSet<u32> = BitSet::new(40);
+    assert_eq!(set.num_words(), 2);
+    assert!(set.insert(0));
+    assert!(set.insert(31));
+    assert!(set.insert(32));
+    assert!(set.insert(39));
+    assert!(!set.insert(39));
+    assert!(set.contains(32));
+    assert!(!set.contains(33));
+    assert_eq!(set.count_ones(), 4);
+    assert_eq!(set.words(), [0x8000_0001, 0x81]);
+    assert_eq!(set.iter().collect::<Vec<_>>(), [0, 31, 32, 39]);
+
+    let mut other: BitSet<u32> = BitSet::new(100);
+    other.insert(31);
+    other.insert(99);
+    assert!(set.union_with(&other));
+    assert_eq!(set.num_words(), 4);
+    assert_eq!((&set - &other).iter().collect::<Vec<_>>(), [0, 32, 39]);
+}
+
+#[test]
+fn iter_intersection_and_difference() {
+    let mut short = BitVector::new(64);
+    short.insert(1);
+    short.insert(2);
+    short.insert(63);
+    let mut long = BitVector::new(200);
+    long.insert(2);
+    long.insert(63);
+    long.insert(64);
+    long.insert(199);
+
+    assert_eq!(short.iter_intersection(&long).collect::<Vec<_>>(), [2, 63]);
+    assert_eq!(long.iter_intersection(&short).collect::<Vec<_>>(), [2, 63]);
+    assert_eq!(short.iter_difference(&long).collect::<Vec<_>>(), [1]);
+    assert_eq!(long.iter_difference(&short).collect::<Vec<_>>(), [64, 199]);
+    assert_eq!(
+        long.iter_difference(&short).collect::<Vec<_>>(),
+        long.difference(&short).iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn insert_and_track() {
+    let mut vec = BitVector::new(0);
+    assert_eq!(vec.max_set_bit(), None);
+    assert!(vec.insert_and_track(5));
+    assert!(!vec.insert_and_track(5));
+    assert_eq!(vec.max_set_bit(), Some(5));
+    assert!(vec.insert_and_track(200));
+    assert_eq!(vec.num_words(), 4);
+    assert_eq!(vec.max_set_bit(), Some(200));
+    assert!(vec.insert_and_track(64));
+    assert_eq!(vec.max_set_bit(), Some(200));
+    assert_eq!(vec.iter().collect::<Vec<_>>(), [5, 64, 200]);
+
+    let mut empty_high = BitVector::new(1000);
+    empty_high.insert(63);
+    assert_eq!(empty_high.max_set_bit(), Some(63));
+}
+
+#[test]
+fn diagonal_and_closure() {
+    // Edges 0 -> 1 -> 2, and 3 on its own.
+    let mut matrix = BitMatrix::new(4);
+    matrix.add(0, 1);
+    matrix.add(1, 2);
+    matrix.set_diagonal();
+    for i in 0..4 {
+        assert!(matrix.contains(i, i));
+    }
+
+    // Reflexive-transitive closure: merge the row of each successor
+    // into the row of each predecessor until nothing changes.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for source in 0..4 {
+            for target in 0..4 {
+                if source != target && matrix.contains(source, target) {
+                    changed |= matrix.merge(target, source);
+                }
+            }
+        }
+    }
+    assert_eq!(matrix.intersection(0, 0), [0, 1, 2]);
+    assert_eq!(matrix.intersection(3, 3), [3]);
+
+    matrix.clear_diagonal();
+    assert_eq!(matrix.intersection(0, 0), [1, 2]);
+    assert_eq!(matrix.intersection(1, 1), [2]);
+    assert_eq!(matrix.row_count_ones(3), 0);
+}
+
+#[test]
+fn matrix_union_and_intersect_with() {
+    let mut a = BitMatrix::new(70);
+    a.add(0, 1);
+    a.add(5, 69);
+    let mut b = BitMatrix::new(70);
+    b.add(0, 1);
+    b.add(2, 64);
+
+    assert!(a.union_with(&b));
+    assert!(!a.union_with(&b));
+    assert!(a.contains(0, 1) && a.contains(2, 64) && a.contains(5, 69));
+
+    assert!(a.intersect_with(&b));
+    assert!(!a.intersect_with(&b));
+    assert!(a == b);
+}
+
+#[test]
+#[should_panic(expected = "different numbers of elements")]
+fn matrix_union_with_wrong_size() {
+    let mut matrix = BitMatrix::new(10);
+    matrix.union_with(&BitMatrix::new(11));
+}
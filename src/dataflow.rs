@@ -0,0 +1,94 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Helpers for monotone dataflow problems over bitsets, where each
+//! node's facts only ever grow until nothing changes.
+
+use std::collections::VecDeque;
+
+use bitvec::BitVector;
+
+/// Computes the fixpoint of a forward dataflow problem over the
+/// graph with nodes `0..nodes`, where `edges(n)` lists the successors
+/// of `n`. The input of a node is the union of the outputs of its
+/// predecessors, and `transfer(n, input)` computes the output of `n`
+/// from its input. Returns the output of every node, indexed by node.
+///
+/// Every node is visited once; after that, a node is only visited
+/// again when the output of one of its predecessors has changed.
+/// Outputs are accumulated with `BitVector::union_with`, so they only
+/// grow and the iteration terminates even if `transfer` is not quite
+/// monotone. `edges` is called once per node, up front.
+pub fn propagate_until_stable<E, T>(nodes: usize, edges: E, mut transfer: T) -> Vec<BitVector>
+where
+    E: Fn(usize) -> Vec<usize>,
+    T: FnMut(usize, &BitVector) -> BitVector,
+{
+    let successors: Vec<Vec<usize>> = (0..nodes).map(&edges).collect();
+    let mut predecessors = vec![vec![]; nodes];
+    for (node, targets) in successors.iter().enumerate() {
+        for &target in targets {
+            assert!(target < nodes, "edge {} -> {} is out of bounds", node, target);
+            predecessors[target].push(node);
+        }
+    }
+
+    let mut outputs = vec![BitVector::new(0); nodes];
+    let mut worklist: VecDeque<usize> = (0..nodes).collect();
+    let mut queued = vec![true; nodes];
+
+    while let Some(node) = worklist.pop_front() {
+        queued[node] = false;
+
+        let mut input = BitVector::new(0);
+        for &pred in &predecessors[node] {
+            input.union_with(&outputs[pred]);
+        }
+
+        let output = transfer(node, &input);
+        if outputs[node].union_with(&output) {
+            for &succ in &successors[node] {
+                if !queued[succ] {
+                    queued[succ] = true;
+                    worklist.push_back(succ);
+                }
+            }
+        }
+    }
+
+    outputs
+}
+
+#[test]
+fn propagate_reaching_nodes() {
+    // 0 -> 1 -> 2 -> 1, 2 -> 3, and 4 on its own. Each node adds
+    // itself, so the fixpoint is the set of nodes that reach it.
+    let edges = |node: usize| match node {
+        0 => vec![1],
+        1 => vec![2],
+        2 => vec![1, 3],
+        _ => vec![],
+    };
+    let mut visits = [0; 5];
+    let outputs = propagate_until_stable(5, edges, |node, input| {
+        visits[node] += 1;
+        let mut output = input.clone();
+        output.grow(node + 1);
+        output.insert(node);
+        output
+    });
+
+    let sets: Vec<Vec<usize>> = outputs.iter().map(|set| set.iter().collect()).collect();
+    assert_eq!(sets, [vec![0], vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2, 3], vec![4]]);
+
+    // The isolated node and the entry are never revisited.
+    assert_eq!(visits[0], 1);
+    assert_eq!(visits[4], 1);
+}
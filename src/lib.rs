@@ -19,5 +19,13 @@ extern crate log;
 #[cfg(feature = "persistent")]
 extern crate dogged;
 
+#[cfg(feature = "petgraph")]
+extern crate petgraph;
+
+#[macro_use]
+mod macros;
+
+pub mod bitvec;
+pub mod dataflow;
 pub mod snapshot_vec;
 pub mod unify;
@@ -0,0 +1,58 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/// Defines a newtype key over `u32` together with its `UnifyKey`
+/// impl, given the value type and the tag:
+///
+/// ```
+/// #[macro_use]
+/// extern crate ena;
+///
+/// unify_key! {
+///     /// A type variable with no value.
+///     pub struct TyVid(u32) => (); "TyVid"
+/// }
+///
+/// # fn main() {
+/// use ena::unify::{InPlaceUnificationTable, UnifyKey};
+/// let mut table: InPlaceUnificationTable<TyVid> = InPlaceUnificationTable::new();
+/// let a = table.new_key(());
+/// assert_eq!(a, TyVid::from_index(0));
+/// assert_eq!(TyVid::tag(), "TyVid");
+/// # }
+/// ```
+///
+/// The key derives `Copy`, `Clone`, `PartialEq`, `Eq`, `Debug` and
+/// `Hash`. Any further attributes (such as doc comments) are passed
+/// through to the struct.
+#[macro_export]
+macro_rules! unify_key {
+    ($(#[$attr:meta])* $vis:vis struct $name:ident(u32) => $value:ty; $tag:expr) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+        $vis struct $name(u32);
+
+        impl $crate::unify::UnifyKey for $name {
+            type Value = $value;
+
+            fn index(&self) -> u32 {
+                self.0
+            }
+
+            fn from_index(u: u32) -> $name {
+                $name(u)
+            }
+
+            fn tag() -> &'static str {
+                $tag
+            }
+        }
+    };
+}
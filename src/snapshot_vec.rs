@@ -21,6 +21,9 @@
 
 use self::UndoLog::*;
 
+#[cfg(feature = "persistent")]
+use dogged::DVec;
+use std::cmp;
 use std::fmt;
 use std::mem;
 use std::ops;
@@ -40,7 +43,12 @@ pub enum UndoLog<D: SnapshotVecDelegate> {
 pub struct SnapshotVec<D: SnapshotVecDelegate> {
     values: Vec<D::Value>,
     undo_log: Vec<UndoLog<D>>,
-    num_open_snapshots: usize,
+    // Length of the undo log when each open snapshot was started,
+    // oldest first.
+    open_snapshots: Vec<usize>,
+    // Minimum number of elements to grow `values` by when it is
+    // full; zero means to use the standard `Vec` growth.
+    growth_hint: usize,
 }
 
 impl<D> fmt::Debug for SnapshotVec<D>
@@ -53,7 +61,8 @@ impl<D> fmt::Debug for SnapshotVec<D>
         fmt.debug_struct("SnapshotVec")
             .field("values", &self.values)
             .field("undo_log", &self.undo_log)
-            .field("num_open_snapshots", &self.num_open_snapshots)
+            .field("open_snapshots", &self.open_snapshots)
+            .field("growth_hint", &self.growth_hint)
             .finish()
     }
 }
@@ -62,6 +71,11 @@ impl<D> fmt::Debug for SnapshotVec<D>
 pub struct Snapshot {
     // Length of the undo log at the time the snapshot was taken.
     pub(crate) length: usize,
+    // Number of values at the time the snapshot was taken.
+    pub(crate) value_count: usize,
+    // Number of snapshots open once this one was started, i.e., its
+    // position in the stack of open snapshots (starting at 1).
+    depth: usize,
 }
 
 pub trait SnapshotVecDelegate {
@@ -77,7 +91,8 @@ impl<D: SnapshotVecDelegate> Default for SnapshotVec<D> {
         SnapshotVec {
             values: Vec::new(),
             undo_log: Vec::new(),
-            num_open_snapshots: 0,
+            open_snapshots: Vec::new(),
+            growth_hint: 0,
         }
     }
 }
@@ -91,12 +106,27 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
         SnapshotVec {
             values: Vec::with_capacity(c),
             undo_log: Vec::new(),
-            num_open_snapshots: 0,
+            open_snapshots: Vec::new(),
+            growth_hint: 0,
         }
     }
 
-    fn in_snapshot(&self) -> bool {
-        self.num_open_snapshots > 0
+    /// When the vector is full and must grow, grow it by at least
+    /// `chunk` elements, rather than using the standard `Vec` growth
+    /// policy. A `chunk` of zero restores the standard policy.
+    pub fn set_growth_hint(&mut self, chunk: usize) {
+        self.growth_hint = chunk;
+    }
+
+    // Makes room for `additional` more values, according to the growth hint.
+    fn grow_for(&mut self, additional: usize) {
+        if self.growth_hint > 0 && self.values.capacity() - self.values.len() < additional {
+            self.values.reserve(cmp::max(additional, self.growth_hint));
+        }
+    }
+
+    pub(crate) fn in_snapshot(&self) -> bool {
+        !self.open_snapshots.is_empty()
     }
 
     pub fn record(&mut self, action: D::Undo) {
@@ -109,8 +139,13 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
         self.values.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
     pub fn push(&mut self, elem: D::Value) -> usize {
         let len = self.values.len();
+        self.grow_for(1);
         self.values.push(elem);
 
         if self.in_snapshot() {
@@ -120,8 +155,16 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
         len
     }
 
-    pub fn get(&self, index: usize) -> &D::Value {
-        &self.values[index]
+    /// Returns the element at the given index, or `None` if it is out
+    /// of bounds (for example, because the push that created it was
+    /// rolled back). Use indexing when the index is known to be valid.
+    pub fn get(&self, index: usize) -> Option<&D::Value> {
+        self.values.get(index)
+    }
+
+    /// Iterates over the elements together with their indices.
+    pub fn iter_enumerated(&self) -> impl Iterator<Item = (usize, &D::Value)> {
+        self.values.iter().enumerate()
     }
 
     /// Reserve space for new values, just like an ordinary vec.
@@ -130,11 +173,19 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
         self.values.reserve(additional);
     }
 
+    /// Shrinks the capacity of the vector (and of the undo log) as
+    /// much as possible, just like an ordinary vec.
+    pub fn shrink_to_fit(&mut self) {
+        // This is not affected by snapshots or anything.
+        self.values.shrink_to_fit();
+        self.undo_log.shrink_to_fit();
+    }
+
     /// Returns a mutable pointer into the vec; whatever changes you make here cannot be undone
     /// automatically, so you should be sure call `record()` with some sort of suitable undo
-    /// action.
-    pub fn get_mut(&mut self, index: usize) -> &mut D::Value {
-        &mut self.values[index]
+    /// action. Returns `None` if the index is out of bounds.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut D::Value> {
+        self.values.get_mut(index)
     }
 
     /// Updates the element at the given index. The old value will saved (and perhaps restored) if
@@ -174,18 +225,68 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
 
     pub fn start_snapshot(&mut self) -> Snapshot {
         let length = self.undo_log.len();
-        self.num_open_snapshots += 1;
-        Snapshot { length: length }
+        self.open_snapshots.push(length);
+        Snapshot { length, value_count: self.values.len(), depth: self.open_snapshots.len() }
+    }
+
+    /// Returns the length of the undo log at the start of each open
+    /// snapshot, from the oldest to the most recent, for debugging.
+    /// The number of entries is the number of open snapshots.
+    pub fn snapshot_lengths(&self) -> Vec<usize> {
+        self.open_snapshots.clone()
     }
 
     pub fn actions_since_snapshot(&self, snapshot: &Snapshot) -> &[UndoLog<D>] {
         &self.undo_log[snapshot.length..]
     }
 
+    /// Maps `index` to its offset among the elements pushed since
+    /// `snapshot` was started: the first element pushed since then has
+    /// offset 0. Returns `None` if the element predates the snapshot
+    /// (or does not exist).
+    pub fn index_in_snapshot(&self, snapshot: &Snapshot, index: usize) -> Option<usize> {
+        if index >= snapshot.value_count && index < self.values.len() {
+            Some(index - snapshot.value_count)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the custom undo actions (those passed to `record`) that
+    /// were logged since the given snapshot was started, oldest first.
+    /// This is a subset of `actions_since_snapshot`.
+    pub fn peek_undo(&self, snapshot: &Snapshot) -> impl Iterator<Item = &D::Undo> + '_ {
+        self.actions_since_snapshot(snapshot)
+            .iter()
+            .filter_map(|action| match *action {
+                Other(ref u) => Some(u),
+                NewElem(..) | SetElem(..) => None,
+            })
+    }
+
+    /// Folds `f` over every custom undo action (those passed to
+    /// `record`) in the undo log, across all open snapshots, without
+    /// consuming them. The actions are visited in the order they were
+    /// recorded, so the most recent comes last. The log is empty, and
+    /// `init` is returned as is, when no snapshot is open.
+    pub fn fold_undo<R>(&self, init: R, mut f: impl FnMut(R, &D::Undo) -> R) -> R {
+        self.undo_log.iter().fold(init, |acc, action| match *action {
+            Other(ref u) => f(acc, u),
+            NewElem(..) | SetElem(..) => acc,
+        })
+    }
+
     fn assert_open_snapshot(&self, snapshot: &Snapshot) {
         // Failures here may indicate a failure to follow a stack discipline.
         assert!(self.undo_log.len() >= snapshot.length);
-        assert!(self.num_open_snapshots > 0);
+        assert!(self.in_snapshot());
+        debug_assert!(
+            snapshot.depth == self.open_snapshots.len(),
+            "snapshot used out of order: it is snapshot {} of the {} open ones, \
+             but only the most recent one can be rolled back or committed",
+            snapshot.depth,
+            self.open_snapshots.len()
+        );
     }
 
     pub fn rollback_to(&mut self, snapshot: Snapshot) {
@@ -210,7 +311,7 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
             }
         }
 
-        self.num_open_snapshots -= 1;
+        self.open_snapshots.pop();
     }
 
     /// Commits all changes since the last snapshot. Of course, they
@@ -220,7 +321,7 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
 
         self.assert_open_snapshot(&snapshot);
 
-        if self.num_open_snapshots == 1 {
+        if self.open_snapshots.len() == 1 {
             // The root snapshot. It's safe to clear the undo log because
             // there's no snapshot further out that we might need to roll back
             // to.
@@ -228,33 +329,50 @@ impl<D: SnapshotVecDelegate> SnapshotVec<D> {
             self.undo_log.clear();
         }
 
-        self.num_open_snapshots -= 1;
+        self.open_snapshots.pop();
+    }
+
+    /// Commits `snapshot` if `keep` returns true and rolls it back
+    /// otherwise. `keep` is given the vector as it stands, with all
+    /// changes made since the snapshot still in place. Returns whether
+    /// the changes were kept.
+    pub fn commit_if<F>(&mut self, snapshot: Snapshot, keep: F) -> bool
+    where
+        F: FnOnce(&Self) -> bool,
+    {
+        let kept = keep(self);
+        if kept {
+            self.commit(snapshot);
+        } else {
+            self.rollback_to(snapshot);
+        }
+        kept
     }
 }
 
 impl<D: SnapshotVecDelegate> ops::Deref for SnapshotVec<D> {
     type Target = [D::Value];
     fn deref(&self) -> &[D::Value] {
-        &*self.values
+        &self.values
     }
 }
 
 impl<D: SnapshotVecDelegate> ops::DerefMut for SnapshotVec<D> {
     fn deref_mut(&mut self) -> &mut [D::Value] {
-        &mut *self.values
+        &mut self.values
     }
 }
 
 impl<D: SnapshotVecDelegate> ops::Index<usize> for SnapshotVec<D> {
     type Output = D::Value;
     fn index(&self, index: usize) -> &D::Value {
-        self.get(index)
+        &self.values[index]
     }
 }
 
 impl<D: SnapshotVecDelegate> ops::IndexMut<usize> for SnapshotVec<D> {
     fn index_mut(&mut self, index: usize) -> &mut D::Value {
-        self.get_mut(index)
+        &mut self.values[index]
     }
 }
 
@@ -263,6 +381,8 @@ impl<D: SnapshotVecDelegate> Extend<D::Value> for SnapshotVec<D> {
     where
         T: IntoIterator<Item = D::Value>,
     {
+        let iterable = iterable.into_iter();
+        self.grow_for(iterable.size_hint().0);
         let initial_len = self.values.len();
         self.values.extend(iterable);
         let final_len = self.values.len();
@@ -282,7 +402,8 @@ where
         SnapshotVec {
             values: self.values.clone(),
             undo_log: self.undo_log.clone(),
-            num_open_snapshots: self.num_open_snapshots,
+            open_snapshots: self.open_snapshots.clone(),
+            growth_hint: self.growth_hint,
         }
     }
 }
@@ -301,6 +422,287 @@ where
     }
 }
 
+/// A variant of `SnapshotVec` backed by a persistent vector, much like
+/// the `Persistent` unification store: starting a snapshot clones the
+/// vector (which is O(1), thanks to structural sharing) and rolling
+/// back simply restores that clone. Requires the `persistent` feature.
+///
+/// It has the same methods as `SnapshotVec`, but since rollback does
+/// not replay an undo log, there is never anything in one: the
+/// actions given to `record` are ignored, `actions_since_snapshot`
+/// and `peek_undo` are always empty, and `snapshot_lengths` reports
+/// a length of zero for each open snapshot. Capacity management
+/// (`with_capacity`, `reserve`, `shrink_to_fit` and
+/// `set_growth_hint`) is not relevant to a persistent vector and does
+/// nothing. The one omission is `Deref` (and `DerefMut`) to a slice,
+/// since the values are not stored contiguously; use indexing or
+/// `iter_enumerated` instead.
+#[cfg(feature = "persistent")]
+pub struct PersistentSnapshotVec<D: SnapshotVecDelegate>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    values: DVec<D::Value>,
+    num_open_snapshots: usize,
+}
+
+/// A snapshot of a `PersistentSnapshotVec`, holding the state to
+/// restore on rollback.
+#[cfg(feature = "persistent")]
+pub struct PersistentSnapshot<D: SnapshotVecDelegate>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    values: DVec<D::Value>,
+    // Number of snapshots open once this one was started, as for
+    // `Snapshot`.
+    depth: usize,
+}
+
+#[cfg(feature = "persistent")]
+impl<D> fmt::Debug for PersistentSnapshotVec<D>
+where
+    D: SnapshotVecDelegate,
+    D::Value: Clone + fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("PersistentSnapshotVec")
+            .field("values", &self.values)
+            .field("num_open_snapshots", &self.num_open_snapshots)
+            .finish()
+    }
+}
+
+// HACK(eddyb) manual impl avoids `Default` bound on `D`.
+#[cfg(feature = "persistent")]
+impl<D: SnapshotVecDelegate> Default for PersistentSnapshotVec<D>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    fn default() -> Self {
+        PersistentSnapshotVec {
+            values: DVec::new(),
+            num_open_snapshots: 0,
+        }
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<D: SnapshotVecDelegate> Clone for PersistentSnapshotVec<D>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    fn clone(&self) -> Self {
+        PersistentSnapshotVec {
+            values: self.values.clone(),
+            num_open_snapshots: self.num_open_snapshots,
+        }
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<D: SnapshotVecDelegate> PersistentSnapshotVec<D>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty vector; the capacity is ignored.
+    pub fn with_capacity(_c: usize) -> Self {
+        Self::default()
+    }
+
+    /// Does nothing: a persistent vector has no growth policy to tune.
+    pub fn set_growth_hint(&mut self, _chunk: usize) {}
+
+    pub(crate) fn in_snapshot(&self) -> bool {
+        self.num_open_snapshots > 0
+    }
+
+    /// Does nothing: rolling back restores the whole vector, so
+    /// there is nothing to record.
+    pub fn record(&mut self, _action: D::Undo) {}
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.len() == 0
+    }
+
+    pub fn push(&mut self, elem: D::Value) -> usize {
+        let len = self.values.len();
+        self.values.push(elem);
+        len
+    }
+
+    /// Returns the element at the given index, or `None` if it is out
+    /// of bounds.
+    pub fn get(&self, index: usize) -> Option<&D::Value> {
+        self.values.get(index)
+    }
+
+    /// Iterates over the elements together with their indices.
+    pub fn iter_enumerated(&self) -> impl Iterator<Item = (usize, &D::Value)> {
+        (0..self.values.len()).map(move |i| (i, &self.values[i]))
+    }
+
+    /// Reserve space for new values. Not obviously relevant to a
+    /// persistent vector, so this does nothing.
+    pub fn reserve(&mut self, _additional: usize) {}
+
+    /// Does nothing, like `reserve`.
+    pub fn shrink_to_fit(&mut self) {}
+
+    /// Returns a mutable pointer into the vec. Unlike with
+    /// `SnapshotVec`, changes made through it are undone on rollback.
+    /// Returns `None` if the index is out of bounds.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut D::Value> {
+        self.values.get_mut(index)
+    }
+
+    /// Updates the element at the given index.
+    pub fn set(&mut self, index: usize, new_elem: D::Value) {
+        self.values[index] = new_elem;
+    }
+
+    /// Updates all elements.
+    pub fn set_all(&mut self, mut new_elems: impl FnMut(usize) -> D::Value) {
+        for i in 0..self.values.len() {
+            self.values[i] = new_elems(i);
+        }
+    }
+
+    pub fn update<OP>(&mut self, index: usize, op: OP)
+    where
+        OP: FnOnce(&mut D::Value),
+    {
+        op(&mut self.values[index]);
+    }
+
+    pub fn start_snapshot(&mut self) -> PersistentSnapshot<D> {
+        self.num_open_snapshots += 1;
+        PersistentSnapshot {
+            values: self.values.clone(),
+            depth: self.num_open_snapshots,
+        }
+    }
+
+    /// Returns a length of zero for each open snapshot, from the
+    /// oldest to the most recent, since there is no undo log.
+    pub fn snapshot_lengths(&self) -> Vec<usize> {
+        vec![0; self.num_open_snapshots]
+    }
+
+    /// Always empty, since there is no undo log.
+    pub fn actions_since_snapshot(&self, _snapshot: &PersistentSnapshot<D>) -> &[UndoLog<D>] {
+        &[]
+    }
+
+    /// Maps `index` to its offset among the elements pushed since
+    /// `snapshot` was started, as for `SnapshotVec`.
+    pub fn index_in_snapshot(&self, snapshot: &PersistentSnapshot<D>, index: usize) -> Option<usize> {
+        let value_count = snapshot.values.len();
+        if index >= value_count && index < self.values.len() {
+            Some(index - value_count)
+        } else {
+            None
+        }
+    }
+
+    /// Always empty, since `record` logs nothing.
+    pub fn peek_undo(&self, _snapshot: &PersistentSnapshot<D>) -> impl Iterator<Item = &D::Undo> + '_ {
+        None.into_iter()
+    }
+
+    /// Returns `init`, since `record` logs nothing.
+    pub fn fold_undo<R>(&self, init: R, _f: impl FnMut(R, &D::Undo) -> R) -> R {
+        init
+    }
+
+    fn assert_open_snapshot(&self, snapshot: &PersistentSnapshot<D>) {
+        // Failures here may indicate a failure to follow a stack discipline.
+        assert!(self.in_snapshot());
+        debug_assert!(
+            snapshot.depth == self.num_open_snapshots,
+            "snapshot used out of order: it is snapshot {} of the {} open ones, \
+             but only the most recent one can be rolled back or committed",
+            snapshot.depth,
+            self.num_open_snapshots
+        );
+    }
+
+    pub fn rollback_to(&mut self, snapshot: PersistentSnapshot<D>) {
+        debug!("rollback_to()");
+        self.assert_open_snapshot(&snapshot);
+        self.values = snapshot.values;
+        self.num_open_snapshots -= 1;
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: PersistentSnapshot<D>) {
+        debug!("commit()");
+        self.assert_open_snapshot(&snapshot);
+        self.num_open_snapshots -= 1;
+    }
+
+    /// Commits `snapshot` if `keep` returns true and rolls it back
+    /// otherwise, as for `SnapshotVec`. Returns whether the changes
+    /// were kept.
+    pub fn commit_if<F>(&mut self, snapshot: PersistentSnapshot<D>, keep: F) -> bool
+    where
+        F: FnOnce(&Self) -> bool,
+    {
+        let kept = keep(self);
+        if kept {
+            self.commit(snapshot);
+        } else {
+            self.rollback_to(snapshot);
+        }
+        kept
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<D: SnapshotVecDelegate> ops::Index<usize> for PersistentSnapshotVec<D>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    type Output = D::Value;
+    fn index(&self, index: usize) -> &D::Value {
+        &self.values[index]
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<D: SnapshotVecDelegate> ops::IndexMut<usize> for PersistentSnapshotVec<D>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    fn index_mut(&mut self, index: usize) -> &mut D::Value {
+        &mut self.values[index]
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<D: SnapshotVecDelegate> Extend<D::Value> for PersistentSnapshotVec<D>
+where
+    D::Value: Clone + fmt::Debug,
+{
+    fn extend<T>(&mut self, iterable: T)
+    where
+        T: IntoIterator<Item = D::Value>,
+    {
+        for value in iterable {
+            self.values.push(value);
+        }
+    }
+}
+
 impl SnapshotVecDelegate for i32 {
     type Value = i32;
     type Undo = ();
@@ -316,12 +718,12 @@ fn basic() {
     vec.push(22);
     vec.push(33);
     assert_eq!(vec.len(), 2);
-    assert_eq!(*vec.get(0), 22);
-    assert_eq!(*vec.get(1), 33);
+    assert_eq!(vec[0], 22);
+    assert_eq!(vec[1], 33);
     vec.set(1, 34);
     assert_eq!(vec.len(), 2);
-    assert_eq!(*vec.get(0), 22);
-    assert_eq!(*vec.get(1), 34);
+    assert_eq!(vec[0], 22);
+    assert_eq!(vec[1], 34);
 
     let snapshot = vec.start_snapshot();
     assert!(vec.in_snapshot());
@@ -330,17 +732,17 @@ fn basic() {
     vec.push(55);
     vec.set(1, 35);
     assert_eq!(vec.len(), 4);
-    assert_eq!(*vec.get(0), 22);
-    assert_eq!(*vec.get(1), 35);
-    assert_eq!(*vec.get(2), 44);
-    assert_eq!(*vec.get(3), 55);
+    assert_eq!(vec[0], 22);
+    assert_eq!(vec[1], 35);
+    assert_eq!(vec[2], 44);
+    assert_eq!(vec[3], 55);
 
     vec.rollback_to(snapshot);
     assert!(!vec.in_snapshot());
 
     assert_eq!(vec.len(), 2);
-    assert_eq!(*vec.get(0), 22);
-    assert_eq!(*vec.get(1), 34);
+    assert_eq!(vec[0], 22);
+    assert_eq!(vec[1], 34);
 }
 
 #[test]
@@ -352,8 +754,8 @@ fn out_of_order() {
     vec.push(33);
     let snapshot2 = vec.start_snapshot();
     vec.push(44);
-    vec.rollback_to(snapshot1); // bogus, but accepted
-    vec.rollback_to(snapshot2); // asserts
+    vec.rollback_to(snapshot1); // bogus; asserts in debug builds
+    vec.rollback_to(snapshot2); // asserts in any case
 }
 
 #[test]
@@ -364,7 +766,232 @@ fn nested_commit_then_rollback() {
     let snapshot2 = vec.start_snapshot();
     vec.set(0, 23);
     vec.commit(snapshot2);
-    assert_eq!(*vec.get(0), 23);
+    assert_eq!(vec[0], 23);
+    vec.rollback_to(snapshot1);
+    assert_eq!(vec[0], 22);
+}
+
+#[test]
+fn shrink_to_fit() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::with_capacity(100);
+    vec.push(22);
+    let snapshot = vec.start_snapshot();
+    vec.push(33);
+    vec.shrink_to_fit();
+    assert!(vec.values.capacity() < 100);
+    assert_eq!(vec.values, [22, 33]);
+    vec.rollback_to(snapshot);
+    assert_eq!(vec.values, [22]);
+}
+
+#[test]
+fn peek_undo() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    vec.record(()); // not in a snapshot, so not logged
+    let snapshot = vec.start_snapshot();
+    vec.record(());
+    vec.push(33);
+    vec.set(0, 23);
+    vec.record(());
+    assert_eq!(vec.actions_since_snapshot(&snapshot).len(), 4);
+    assert_eq!(vec.peek_undo(&snapshot).count(), 2);
+    vec.rollback_to(snapshot);
+}
+
+#[cfg(feature = "persistent")]
+#[test]
+fn persistent_basic() {
+    let mut vec: PersistentSnapshotVec<i32> = PersistentSnapshotVec::default();
+    assert!(vec.is_empty());
+    vec.push(22);
+    vec.push(33);
+    vec.set(1, 34);
+
+    let snapshot = vec.start_snapshot();
+    vec.push(44);
+    vec.update(0, |v| *v += 1);
+    vec[1] = 35;
+    assert_eq!(vec.len(), 3);
+    assert_eq!(vec[0], 23);
+    assert_eq!(vec[1], 35);
+
+    vec.rollback_to(snapshot);
+    assert!(!vec.in_snapshot());
+    assert_eq!(vec.len(), 2);
+    assert_eq!(vec[0], 22);
+    assert_eq!(vec[1], 34);
+}
+
+#[cfg(feature = "persistent")]
+#[test]
+fn persistent_nested_commit_then_rollback() {
+    let mut vec: PersistentSnapshotVec<i32> = PersistentSnapshotVec::default();
+    vec.push(22);
+    let snapshot1 = vec.start_snapshot();
+    let snapshot2 = vec.start_snapshot();
+    vec.set(0, 23);
+    vec.commit(snapshot2);
+    assert_eq!(vec[0], 23);
     vec.rollback_to(snapshot1);
-    assert_eq!(*vec.get(0), 22);
+    assert_eq!(vec[0], 22);
+}
+
+#[cfg(feature = "persistent")]
+#[test]
+fn persistent_matches_snapshot_vec() {
+    let mut vec: PersistentSnapshotVec<i32> = PersistentSnapshotVec::with_capacity(10);
+    vec.set_growth_hint(4);
+    vec.push(22);
+    let outer = vec.start_snapshot();
+    let inner = vec.start_snapshot();
+    vec.push(33);
+    vec.record(());
+    assert_eq!(vec.snapshot_lengths(), [0, 0]);
+    assert!(vec.actions_since_snapshot(&inner).is_empty());
+    assert_eq!(vec.peek_undo(&inner).count(), 0);
+    assert_eq!(vec.fold_undo(7, |n, _| n + 1), 7);
+    assert_eq!(vec.index_in_snapshot(&inner, 1), Some(0));
+    assert_eq!(vec.index_in_snapshot(&inner, 0), None);
+    let pairs: Vec<_> = vec.iter_enumerated().map(|(i, &v)| (i, v)).collect();
+    assert_eq!(pairs, [(0, 22), (1, 33)]);
+
+    assert!(!vec.commit_if(inner, |vec| vec.len() > 2));
+    assert_eq!(vec.len(), 1);
+    vec.shrink_to_fit();
+    vec.commit(outer);
+    assert!(!vec.in_snapshot());
+}
+
+#[cfg(all(feature = "persistent", debug_assertions))]
+#[test]
+#[should_panic(expected = "snapshot used out of order")]
+fn persistent_out_of_order() {
+    let mut vec: PersistentSnapshotVec<i32> = PersistentSnapshotVec::default();
+    let snapshot1 = vec.start_snapshot();
+    let _snapshot2 = vec.start_snapshot();
+    vec.rollback_to(snapshot1);
+}
+
+#[test]
+fn get_out_of_bounds() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::new();
+    vec.push(22);
+
+    let snapshot = vec.start_snapshot();
+    vec.push(33);
+    assert_eq!(vec.get(1), Some(&33));
+    vec.rollback_to(snapshot);
+
+    assert_eq!(vec.get(0), Some(&22));
+    assert_eq!(vec.get(1), None);
+    *vec.get_mut(0).unwrap() += 1;
+    assert_eq!(vec[0], 23);
+    assert!(vec.get_mut(1).is_none());
+}
+
+#[test]
+fn growth_hint() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::new();
+    vec.set_growth_hint(100);
+    vec.push(22);
+    assert!(vec.values.capacity() >= 100);
+    let capacity = vec.values.capacity();
+    vec.extend(0..capacity as i32 - 1);
+    assert_eq!(vec.values.capacity(), capacity);
+    vec.push(33);
+    assert!(vec.values.capacity() >= capacity + 100);
+    assert_eq!(vec.len(), capacity + 1);
+}
+
+#[test]
+fn fold_undo() {
+    struct Numbered;
+
+    impl SnapshotVecDelegate for Numbered {
+        type Value = i32;
+        type Undo = u32;
+
+        fn reverse(_: &mut Vec<i32>, _: u32) {}
+    }
+
+    let mut vec: SnapshotVec<Numbered> = SnapshotVec::new();
+    vec.record(0); // not in a snapshot, so not logged
+    assert_eq!(vec.fold_undo(vec![], |mut acc, &u| { acc.push(u); acc }), vec![]);
+
+    let outer = vec.start_snapshot();
+    vec.record(1);
+    vec.push(22);
+    let inner = vec.start_snapshot();
+    vec.record(2);
+    vec.record(3);
+    assert_eq!(vec.fold_undo(vec![], |mut acc, &u| { acc.push(u); acc }), vec![1, 2, 3]);
+    assert_eq!(vec.fold_undo(0, |acc, &u| acc + u), 6);
+
+    vec.rollback_to(inner);
+    assert_eq!(vec.fold_undo(0, |acc, &u| acc + u), 1);
+    vec.rollback_to(outer);
+}
+
+#[test]
+fn index_in_snapshot() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::new();
+    vec.push(22);
+    vec.push(33);
+    let snapshot = vec.start_snapshot();
+    vec.set(0, 23);
+    vec.push(44);
+    vec.push(55);
+    assert_eq!(vec.index_in_snapshot(&snapshot, 0), None);
+    assert_eq!(vec.index_in_snapshot(&snapshot, 1), None);
+    assert_eq!(vec.index_in_snapshot(&snapshot, 2), Some(0));
+    assert_eq!(vec.index_in_snapshot(&snapshot, 3), Some(1));
+    assert_eq!(vec.index_in_snapshot(&snapshot, 4), None);
+    vec.rollback_to(snapshot);
+}
+
+#[test]
+fn commit_if() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+
+    let snapshot = vec.start_snapshot();
+    vec.set(0, 10);
+    vec.push(5);
+    let kept = vec.commit_if(snapshot, |vec| vec.iter().sum::<i32>() < 22);
+    assert!(kept);
+    assert_eq!(vec.values, [10, 5]);
+    assert!(!vec.in_snapshot());
+
+    let snapshot = vec.start_snapshot();
+    vec.set(1, 50);
+    let kept = vec.commit_if(snapshot, |vec| vec.iter().sum::<i32>() < 15);
+    assert!(!kept);
+    assert_eq!(vec.values, [10, 5]);
+    assert!(!vec.in_snapshot());
+}
+
+#[test]
+fn iter_enumerated() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    vec.push(33);
+    let pairs: Vec<_> = vec.iter_enumerated().map(|(i, &v)| (i, v)).collect();
+    assert_eq!(pairs, [(0, 22), (1, 33)]);
+}
+
+#[test]
+fn snapshot_lengths() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    assert_eq!(vec.snapshot_lengths(), []);
+    let outer = vec.start_snapshot();
+    vec.push(33);
+    vec.set(0, 23);
+    let inner = vec.start_snapshot();
+    assert_eq!(vec.snapshot_lengths(), [0, 2]);
+    vec.rollback_to(inner);
+    assert_eq!(vec.snapshot_lengths(), [0]);
+    vec.commit(outer);
+    assert_eq!(vec.snapshot_lengths(), []);
 }
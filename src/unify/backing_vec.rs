@@ -2,7 +2,7 @@
 use dogged::DVec;
 use snapshot_vec as sv;
 use std::ops;
-use std::ops::RangeInclusive;
+use std::ops::Range;
 use std::marker::PhantomData;
 
 use super::{VarValue, UnifyKey, UnifyValue};
@@ -31,8 +31,17 @@ pub trait UnificationStore:
 
     fn commit(&mut self, snapshot: Self::Snapshot);
 
-    fn values_since_snapshot(&mut self, snapshot: &Self::Snapshot) -> RangeInclusive<usize> {
-        snapshot.len()..=self.len()
+    /// Indicates whether a snapshot is open, i.e., one was started and
+    /// has not been rolled back or committed yet. Stores that do not
+    /// track their snapshots keep the default, which answers false.
+    fn in_snapshot(&self) -> bool {
+        false
+    }
+
+    /// The indices of the values that were added since the given
+    /// snapshot was started.
+    fn values_since_snapshot(&self, snapshot: &Self::Snapshot) -> Range<usize> {
+        snapshot.len()..self.len()
     }
 
     fn reset_unifications(
@@ -42,8 +51,19 @@ pub trait UnificationStore:
 
     fn push(&mut self, value: VarValue<Self::Key>);
 
+    /// Pushes all the given values, as if by repeated calls to `push`.
+    fn extend(&mut self, values: impl Iterator<Item = VarValue<Self::Key>>) {
+        for value in values {
+            self.push(value);
+        }
+    }
+
     fn reserve(&mut self, num_new_values: usize);
 
+    /// Releases any excess capacity. The default does nothing, for
+    /// stores where that is not meaningful.
+    fn shrink_to_fit(&mut self) {}
+
     fn update<F>(&mut self, index: usize, op: F)
         where F: FnOnce(&mut VarValue<Self::Key>);
 
@@ -69,7 +89,7 @@ impl<K: UnifyKey> Default for InPlace<K> {
 impl Measurable for sv::Snapshot {
     #[inline]
     fn len(&self) -> usize {
-        self.length
+        self.value_count
     }
 }
 
@@ -100,6 +120,11 @@ impl<K: UnifyKey> UnificationStore for InPlace<K> {
         self.values.commit(snapshot);
     }
 
+    #[inline]
+    fn in_snapshot(&self) -> bool {
+        self.values.in_snapshot()
+    }
+
     #[inline]
     fn reset_unifications(
         &mut self,
@@ -113,11 +138,21 @@ impl<K: UnifyKey> UnificationStore for InPlace<K> {
         self.values.push(value);
     }
 
+    #[inline]
+    fn extend(&mut self, values: impl Iterator<Item = VarValue<Self::Key>>) {
+        self.values.extend(values);
+    }
+
     #[inline]
     fn reserve(&mut self, num_new_values: usize) {
         self.values.reserve(num_new_values);
     }
 
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.values.shrink_to_fit();
+    }
+
     #[inline]
     fn update<F>(&mut self, index: usize, op: F)
         where F: FnOnce(&mut VarValue<Self::Key>)
@@ -135,6 +170,10 @@ impl<K> ops::Index<usize> for InPlace<K>
     }
 }
 
+/// No custom undo actions are needed: `SnapshotVec::update` already
+/// logs the previous `VarValue` whenever a snapshot is open, so every
+/// change made through `UnificationStore::update` is undone on
+/// rollback.
 #[derive(Copy, Clone, Debug)]
 struct Delegate<K>(PhantomData<K>);
 
@@ -148,14 +187,33 @@ impl<K: UnifyKey> sv::SnapshotVecDelegate for Delegate<K> {
 #[cfg(feature = "persistent")]
 #[derive(Clone, Debug)]
 pub struct Persistent<K: UnifyKey> {
-    values: DVec<VarValue<K>>
+    values: DVec<VarValue<K>>,
+    // The number of open snapshots. A snapshot is a copy of the store
+    // taken before this was incremented, so rolling back to it also
+    // restores the count.
+    open_snapshots: usize,
 }
 
 // HACK(eddyb) manual impl avoids `Default` bound on `K`.
 #[cfg(feature = "persistent")]
 impl<K: UnifyKey> Default for Persistent<K> {
     fn default() -> Self {
-        Persistent { values: DVec::new() }
+        Persistent { values: DVec::new(), open_snapshots: 0 }
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<K: UnifyKey> Persistent<K> {
+    fn assert_open_snapshot(&self, snapshot: &Self) {
+        // Failures here may indicate a failure to follow a stack discipline.
+        assert!(self.in_snapshot());
+        debug_assert!(
+            snapshot.open_snapshots + 1 == self.open_snapshots,
+            "snapshot used out of order: it is snapshot {} of the {} open ones, \
+             but only the most recent one can be rolled back or committed",
+            snapshot.open_snapshots + 1,
+            self.open_snapshots
+        );
     }
 }
 
@@ -175,16 +233,26 @@ impl<K: UnifyKey> UnificationStore for Persistent<K> {
 
     #[inline]
     fn start_snapshot(&mut self) -> Self::Snapshot {
-        self.clone()
+        let snapshot = self.clone();
+        self.open_snapshots += 1;
+        snapshot
     }
 
     #[inline]
     fn rollback_to(&mut self, snapshot: Self::Snapshot) {
+        self.assert_open_snapshot(&snapshot);
         *self = snapshot;
     }
 
     #[inline]
-    fn commit(&mut self, _snapshot: Self::Snapshot) {
+    fn commit(&mut self, snapshot: Self::Snapshot) {
+        self.assert_open_snapshot(&snapshot);
+        self.open_snapshots -= 1;
+    }
+
+    #[inline]
+    fn in_snapshot(&self) -> bool {
+        self.open_snapshots > 0
     }
 
     #[inline]
@@ -205,11 +273,17 @@ impl<K: UnifyKey> UnificationStore for Persistent<K> {
         self.values.push(value);
     }
 
+    // `extend` keeps the default, one `push` at a time: `DVec` has
+    // no batch operation to forward to.
+
     #[inline]
     fn reserve(&mut self, _num_new_values: usize) {
         // not obviously relevant to DVec.
     }
 
+    // `shrink_to_fit` keeps the default, which does nothing: like
+    // `reserve`, it is not obviously relevant to DVec.
+
     #[inline]
     fn update<F>(&mut self, index: usize, op: F)
         where F: FnOnce(&mut VarValue<Self::Key>)
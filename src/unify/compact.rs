@@ -0,0 +1,151 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A compact binary encoding of the partition and values of a
+//! unification table, for caching solved tables.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::{UnificationStore, UnificationTable, UnifyKey, UnifyValue, VarValue};
+
+impl<S, K, V> UnificationTable<S>
+where
+    S: UnificationStore<Key = K, Value = V>,
+    K: UnifyKey<Value = V>,
+    V: UnifyValue + Eq + Hash,
+{
+    /// Appends an encoding of the partition and values of the table
+    /// to `out`, from which `deserialize_compact` rebuilds a table
+    /// with the same keys, unions and values. Only the classes are
+    /// kept, not the shape of the trees, nor snapshots or any of the
+    /// table's settings. The table is fully path-compressed along the
+    /// way.
+    ///
+    /// The encoding is: the number of keys; for each key, the number
+    /// of its class (classes are numbered in the order of their first
+    /// key); the number of classes; for each class, the number of its
+    /// value; the number of distinct values; and each distinct value,
+    /// as written by `encode`. Numbers are LEB128 varints.
+    pub fn serialize_compact<F>(&mut self, out: &mut Vec<u8>, mut encode: F)
+    where
+        F: FnMut(&V, &mut Vec<u8>),
+    {
+        let len = self.len();
+        write_varint(out, len as u64);
+        let mut class_of_root = HashMap::new();
+        let mut roots = Vec::new();
+        for i in 0..len as u32 {
+            let root = self.find(K::from_index(i));
+            let class = *class_of_root.entry(root.index()).or_insert_with(|| {
+                roots.push(root);
+                roots.len() - 1
+            });
+            write_varint(out, class as u64);
+        }
+
+        let this = &*self;
+        write_varint(out, roots.len() as u64);
+        let mut value_ids = HashMap::new();
+        let mut values = Vec::new();
+        for &root in &roots {
+            let value = &this.value(root).value;
+            let id = *value_ids.entry(value).or_insert_with(|| {
+                values.push(value);
+                values.len() - 1
+            });
+            write_varint(out, id as u64);
+        }
+
+        write_varint(out, values.len() as u64);
+        for value in values {
+            encode(value, out);
+        }
+    }
+
+    /// Rebuilds a table from the output of `serialize_compact`. Each
+    /// value is read with `decode`, which is given the remaining input
+    /// and must advance it past the value. Returns `None` if the input
+    /// is malformed or has bytes left over, or if `decode` fails.
+    pub fn deserialize_compact<F>(mut bytes: &[u8], mut decode: F) -> Option<Self>
+    where
+        F: FnMut(&mut &[u8]) -> Option<V>,
+    {
+        let bytes = &mut bytes;
+        let len = read_varint(bytes)?;
+        let mut classes = Vec::new();
+        for _ in 0..len {
+            classes.push(read_varint(bytes)? as usize);
+        }
+        let num_classes = read_varint(bytes)?;
+        if num_classes > len {
+            return None;
+        }
+        let mut class_values = Vec::new();
+        for _ in 0..num_classes {
+            class_values.push(read_varint(bytes)? as usize);
+        }
+        // Every value belongs to some class, so this bounds the loop
+        // below even if `decode` consumes no input.
+        let num_values = read_varint(bytes)?;
+        if num_values > num_classes {
+            return None;
+        }
+        let mut values = Vec::new();
+        for _ in 0..num_values {
+            values.push(decode(bytes)?);
+        }
+        if !bytes.is_empty() {
+            return None;
+        }
+
+        // The first key of each class becomes its root; every other
+        // key points directly at it.
+        let mut roots = vec![None; class_values.len()];
+        let mut sizes = vec![0; class_values.len()];
+        for (i, &class) in classes.iter().enumerate() {
+            if class >= class_values.len() || class_values[class] >= values.len() {
+                return None;
+            }
+            roots[class].get_or_insert(i);
+            sizes[class] += 1;
+        }
+
+        let mut table = Self::default();
+        for &class in &classes {
+            let root = roots[class].unwrap();
+            let rank = if sizes[class] > 1 { 1 } else { 0 };
+            let value = values[class_values[class]].clone();
+            table.values.push(VarValue::new(K::from_index(root as u32), value, rank));
+        }
+        Some(table)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
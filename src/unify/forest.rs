@@ -0,0 +1,37 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Conversion of a unification table into a `petgraph` graph, for
+//! visualizing the union-find forest.
+
+use petgraph::Graph;
+use petgraph::graph::NodeIndex;
+
+use super::{UnificationStore, UnificationTable, UnifyKey};
+
+impl<S: UnificationStore> UnificationTable<S> {
+    /// Builds a graph of the union-find forest as it is currently
+    /// stored. There is one node per key, with index equal to the
+    /// key's index, and an edge from each non-root key to its parent.
+    /// No path compression is done, so the graph shows the actual
+    /// links that `find` would have to follow.
+    pub fn to_forest_graph(&self) -> Graph<S::Key, ()> {
+        let mut graph = Graph::with_capacity(self.len(), self.len());
+        for i in 0..self.len() {
+            graph.add_node(S::Key::from_index(i as u32));
+        }
+        for (i, parent) in self.raw_parents().into_iter().enumerate() {
+            if parent as usize != i {
+                graph.add_edge(NodeIndex::new(i), NodeIndex::new(parent as usize), ());
+            }
+        }
+        graph
+    }
+}
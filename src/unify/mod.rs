@@ -19,7 +19,8 @@
 //! these values are cheaply cloneable (ideally, `Copy`), and some of
 //! the interfaces are oriented around that assumption. If you just
 //! want the classical "union-find" algorithm where you group things
-//! into sets, use the `Value` type of `()`.
+//! into sets, use the `Value` type of `()`. Since `()` is zero-sized,
+//! it adds no storage to each key.
 //!
 //! When you have keys with non-trivial values, you must also define
 //! how those values can be merged. As part of doing this, you can
@@ -31,15 +32,27 @@
 //! The best way to see how it is used is to read the `tests.rs` file;
 //! search for e.g. `UnitKey`.
 
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::marker;
-use std::fmt::Debug;
+use std::mem;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::sync::Arc;
+
+use bitvec::BitVector;
 
 mod backing_vec;
+mod compact;
 pub use self::backing_vec::{InPlace, UnificationStore};
 
 #[cfg(feature = "persistent")]
 pub use self::backing_vec::Persistent;
 
+#[cfg(feature = "petgraph")]
+mod forest;
+
 
 #[cfg(test)]
 mod tests;
@@ -112,6 +125,28 @@ pub trait UnifyValue: Clone + Debug {
     /// Given two values, produce a new value that combines them.
     /// If that is not possible, produce an error.
     fn unify_values(value1: &Self, value2: &Self) -> Result<Self, Self::Error>;
+
+    /// Like `unify_values`, but also given some information about the
+    /// two classes being merged (see `UnifyCtx`). This is what the
+    /// table invokes when unioning two keys; implement it if your
+    /// decision depends on more than the values. The default simply
+    /// invokes `unify_values`.
+    #[allow(unused_variables)]
+    fn unify_values_ctx(value1: &Self, value2: &Self, ctx: UnifyCtx) -> Result<Self, Self::Error> {
+        Self::unify_values(value1, value2)
+    }
+}
+
+/// Information about the two classes being merged, given to
+/// `UnifyValue::unify_values_ctx`. The ranks are those of the roots
+/// of the classes whose values are `value1` and `value2`
+/// respectively; a rank is an upper bound on the class's tree height
+/// (and the log of a lower bound on its size), and is 0 for a class
+/// that has never been merged.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnifyCtx {
+    pub rank1: u32,
+    pub rank2: u32,
 }
 
 /// A convenient helper for unification values which must be equal or
@@ -123,22 +158,127 @@ pub trait UnifyValue: Clone + Debug {
 ///
 /// Any type which implements `EqUnifyValue` automatially implements
 /// `UnifyValue`; if the two values are equal, merging is permitted.
-/// Otherwise, the error `(v1, v2)` is returned, where `v1` and `v2`
-/// are the two unequal values.
+/// Otherwise, a `Mismatch` error carrying the two unequal values is
+/// returned.
 pub trait EqUnifyValue: Eq + Clone + Debug {}
 
 impl<T: EqUnifyValue> UnifyValue for T {
-    type Error = (T, T);
+    type Error = Mismatch<T>;
 
     fn unify_values(value1: &Self, value2: &Self) -> Result<Self, Self::Error> {
         if value1 == value2 {
             Ok(value1.clone())
         } else {
-            Err((value1.clone(), value2.clone()))
+            Err(Mismatch {
+                expected: value1.clone(),
+                found: value2.clone(),
+            })
         }
     }
 }
 
+/// The error produced when unifying two unequal `EqUnifyValue`s.
+/// `expected` is the value that was already present (for
+/// `unify_var_var`, the value of the first key) and `found` is the
+/// value it was unified with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Mismatch<V> {
+    pub expected: V,
+    pub found: V,
+}
+
+impl<V: Debug> fmt::Display for Mismatch<V> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "expected `{:?}`, found `{:?}`", self.expected, self.found)
+    }
+}
+
+impl<V: Debug> Error for Mismatch<V> {}
+
+/// An error from unification together with the tag of the key type
+/// (see `UnifyKey::tag`), so that a solver with several tables can
+/// tell which one failed. Produced by `unify_var_var_tagged` and
+/// `unify_var_value_tagged`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TaggedError<E> {
+    pub tag: &'static str,
+    pub error: E,
+}
+
+impl<E: fmt::Display> fmt::Display for TaggedError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} unification failed: {}", self.tag, self.error)
+    }
+}
+
+impl<E: Error + 'static> Error for TaggedError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// An error from unifying a key with a value, together with the key,
+/// so that diagnostics can point at the right variable. Produced by
+/// `unify_var_value_checked`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnifyError<K, E> {
+    pub key: K,
+    pub error: E,
+}
+
+impl<K: Debug, E: fmt::Display> fmt::Display for UnifyError<K, E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "failed to unify `{:?}`: {}", self.key, self.error)
+    }
+}
+
+impl<K: Debug, E: Error + 'static> Error for UnifyError<K, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// What the `try_` methods of a table (`try_find`, `try_probe_value`
+/// and `try_unify_var_var`) do when given a key that is out of
+/// bounds, e.g. one created in a snapshot that was since rolled back.
+/// Chosen with `UnificationTable::with_bounds_check`; the default is
+/// `Panic`. The other methods always panic on such keys.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OnOob {
+    /// Panic, like the other methods.
+    #[default]
+    Panic,
+
+    /// Use the last key of the table in its place. This still panics
+    /// if the table has no keys.
+    Saturate,
+
+    /// Return an `OutOfBounds` error carrying the key.
+    Error,
+}
+
+/// The error from the `try_` methods of a table whose policy is
+/// `OnOob::Error`, carrying the out-of-bounds key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OutOfBounds<K>(pub K);
+
+impl<K: Debug> fmt::Display for OutOfBounds<K> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "key `{:?}` is out of bounds", self.0)
+    }
+}
+
+impl<K: Debug> Error for OutOfBounds<K> {}
+
+/// A failed unification of two keys, as collected by
+/// `unify_var_var_collecting`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Conflict<K, E> {
+    pub a: K,
+    pub b: K,
+    pub error: E,
+}
+
 /// A struct which can never be instantiated. Used
 /// for the error type for infallible cases.
 #[derive(Debug)]
@@ -154,11 +294,21 @@ pub struct NoError {
 /// to keep the DAG relatively balanced, which helps keep the running
 /// time of the algorithm under control. For more information, see
 /// <http://en.wikipedia.org/wiki/Disjoint-set_data_structure>.
+///
+/// With union-by-rank, the rank of a root is at most the log of the
+/// number of keys, so it is stored in a single byte. This shrinks
+/// `VarValue`s whose value is small enough to share a word with the
+/// rank: a `u32` key with a one-byte value takes 8 bytes rather than
+/// 12. It does not help when the value is `()`, since a `u32` key
+/// and a rank are padded out to 8 bytes either way. (If
+/// `order_roots` overrides the choice of root, ranks can grow faster;
+/// they then saturate, which only affects balancing, never
+/// correctness.)
 #[derive(PartialEq, Clone, Debug)]
 pub struct VarValue<K: UnifyKey> { // FIXME pub
     parent: K, // if equal to self, this is a root
     value: K::Value, // value assigned (only relevant to root)
-    rank: u32, // max depth (only relevant to root)
+    rank: u8, // max depth (only relevant to root)
 }
 
 /// Table of unification keys and their values. You must define a key type K
@@ -174,10 +324,182 @@ pub struct VarValue<K: UnifyKey> { // FIXME pub
 ///     cloning the table is an O(1) operation.
 ///   - This implies that ordinary operations are quite a bit slower though.
 ///   - Requires the `persistent` feature be selected in your Cargo.toml file.
-#[derive(Clone, Debug, Default)]
+#[derive(Debug)]
 pub struct UnificationTable<S: UnificationStore> {
     /// Indicates the current value of each key.
     values: S,
+
+    /// If recording, the operations performed so far (see
+    /// `with_recording`).
+    log: Option<Vec<Op<S::Key>>>,
+
+    /// If set, chooses the new root when unioning two roots of equal
+    /// rank (see `prefer`).
+    prefer: Option<PreferRoot<S::Key>>,
+
+    /// If collecting statistics, the statistics so far (see
+    /// `with_stats`).
+    stats: Option<FindStats>,
+
+    /// If set, the function to call whenever the number of keys
+    /// reaches a multiple of its threshold (see `on_growth`).
+    growth: Option<GrowthHook>,
+
+    /// What the `try_` methods do with out-of-bounds keys (see
+    /// `with_bounds_check`).
+    bounds_check: OnOob,
+}
+
+// Manual impl since the growth hook cannot be cloned; the clone has
+// none.
+impl<S: UnificationStore> Clone for UnificationTable<S> {
+    fn clone(&self) -> Self {
+        UnificationTable {
+            values: self.values.clone(),
+            log: self.log.clone(),
+            prefer: self.prefer.clone(),
+            stats: self.stats,
+            growth: None,
+            bounds_check: self.bounds_check,
+        }
+    }
+}
+
+// HACK(eddyb) manual impl avoids `Default` bound on `S::Key`.
+impl<S: UnificationStore> Default for UnificationTable<S> {
+    fn default() -> Self {
+        UnificationTable {
+            values: S::default(),
+            log: None,
+            prefer: None,
+            stats: None,
+            growth: None,
+            bounds_check: OnOob::default(),
+        }
+    }
+}
+
+/// Statistics about the paths walked to find the root of a key,
+/// collected by a table created with `with_stats`. Every operation
+/// that looks up a root counts, not only `find`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FindStats {
+    /// The number of root lookups.
+    pub calls: u64,
+
+    /// The total number of parent links followed, over all lookups.
+    pub total_steps: u64,
+
+    /// The largest number of parent links followed by one lookup.
+    pub max_steps: u64,
+}
+
+impl FindStats {
+    fn record(&mut self, steps: u64) {
+        self.calls += 1;
+        self.total_steps += steps;
+        self.max_steps = cmp::max(self.max_steps, steps);
+    }
+}
+
+/// The values stored through `UnificationTable::unify_var_value_interned`,
+/// one per distinct value. The interner belongs to the caller rather
+/// than to a table, so tables that never intern pay nothing for it,
+/// and one interner can be shared by several tables. It is not
+/// affected by snapshots: values interned inside a snapshot stay
+/// alive after it is rolled back, until the interner is cleared or
+/// dropped.
+#[derive(Clone, Debug)]
+pub struct Interner<V> {
+    values: HashSet<V>,
+}
+
+impl<V: Eq + Hash + Clone> Interner<V> {
+    pub fn new() -> Self {
+        Interner { values: HashSet::new() }
+    }
+
+    /// Returns a clone of the value equal to `value` that was
+    /// interned first, interning `value` if there is none.
+    pub fn intern(&mut self, value: V) -> V {
+        if let Some(interned) = self.values.get(&value) {
+            return interned.clone();
+        }
+        self.values.insert(value.clone());
+        value
+    }
+
+    /// Returns the number of distinct values interned.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Forgets the interned values; the values already stored in
+    /// tables are not affected.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+impl<V: Eq + Hash + Clone> Default for Interner<V> {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+/// Chooses between two roots of equal rank; see
+/// `UnificationTable::prefer`. Shared, so that clones of a table keep
+/// the policy.
+#[derive(Clone)]
+struct PreferRoot<K>(Arc<dyn Fn(K, K) -> K + Send + Sync>);
+
+impl<K> Debug for PreferRoot<K> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("PreferRoot")
+    }
+}
+
+/// A function registered with `UnificationTable::on_growth`, with the
+/// threshold at which to call it.
+struct GrowthHook {
+    threshold: usize,
+    hook: Box<dyn FnMut(usize) + Send + Sync>,
+}
+
+impl Debug for GrowthHook {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GrowthHook")
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+/// A mutating operation on a unification table, as captured by a
+/// table created with `UnificationTable::with_recording`. A log of
+/// these can be fed to `UnificationTable::replay` to reproduce the
+/// table.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op<K: UnifyKey> {
+    /// `new_key` was invoked with the given value.
+    NewKey(K::Value),
+
+    /// `new_key_with` was invoked with the given value and rank.
+    NewKeyWithRank(K::Value, u32),
+
+    /// `unify_var_var` was invoked with the given keys.
+    UnifyVarVar(K, K),
+
+    /// `unify_var_value` was invoked with the given key and value.
+    UnifyVarValue(K, K::Value),
+
+    /// The value of the key's class was overwritten with the given
+    /// value, bypassing `unify_values` (by `probe_or_set` or
+    /// `unset_value`).
+    SetValue(K, K::Value),
 }
 
 /// A unification table that uses an "in-place" vector.
@@ -197,24 +519,123 @@ pub struct Snapshot<S: UnificationStore> {
     snapshot: S::Snapshot,
 }
 
+/// An immutable copy of the partition of a unification table, as
+/// returned by `UnificationTable::read_snapshot`. Every key points
+/// directly at its root, so queries take `&self` and are O(1); the
+/// snapshot can thus be shared between threads (if `K` and its values
+/// are `Sync`) while the table it came from goes on changing.
+#[derive(Clone, Debug)]
+pub struct ReadSnapshot<K: UnifyKey> {
+    values: Vec<VarValue<K>>,
+}
+
+impl<K: UnifyKey> ReadSnapshot<K> {
+    /// Returns the root key of `id`, as of when the snapshot was taken.
+    pub fn find<K1: Into<K>>(&self, id: K1) -> K {
+        self.values[id.into().index() as usize].parent
+    }
+
+    /// Indicates whether the two keys were unioned together when the
+    /// snapshot was taken.
+    pub fn unioned<K1: Into<K>, K2: Into<K>>(&self, a_id: K1, b_id: K2) -> bool {
+        self.find(a_id) == self.find(b_id)
+    }
+
+    /// Returns the value of the class of `id`, as of when the snapshot
+    /// was taken.
+    pub fn probe_value<K1: Into<K>>(&self, id: K1) -> &K::Value {
+        &self.values[self.find(id).index() as usize].value
+    }
+
+    /// Returns the number of keys in the snapshot; keys created in
+    /// the table afterwards are not included.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if the snapshot has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// A handle on one key of a unification table, as returned by
+/// `UnificationTable::entry`, for operating on that key repeatedly.
+/// Each method simply forwards to the table method of the same name
+/// (`find`, `probe_value`, `unify_var_value` and `unify_var_var`).
+pub struct Entry<'a, S: UnificationStore + 'a> {
+    table: &'a mut UnificationTable<S>,
+    key: S::Key,
+}
+
+impl<'a, S, K, V> Entry<'a, S>
+where
+    S: UnificationStore<Key = K, Value = V>,
+    K: UnifyKey<Value = V>,
+    V: UnifyValue,
+{
+    /// The key this entry was created for.
+    pub fn key(&self) -> K {
+        self.key
+    }
+
+    /// The current root key of the entry's class.
+    pub fn find(&mut self) -> K {
+        self.table.find(self.key)
+    }
+
+    /// The current value of the entry's class.
+    pub fn value(&mut self) -> V {
+        self.table.probe_value(self.key)
+    }
+
+    /// Unifies the entry's class with `value`; on success, returns
+    /// the entry again so that calls can be chained.
+    pub fn set(&mut self, value: V) -> Result<&mut Self, V::Error> {
+        self.table.unify_var_value(self.key, value)?;
+        Ok(self)
+    }
+
+    /// Unifies the entry's class with that of `other`; on success,
+    /// returns the entry again so that calls can be chained.
+    pub fn union_with<K1: Into<K>>(&mut self, other: K1) -> Result<&mut Self, V::Error> {
+        self.table.unify_var_var(self.key, other)?;
+        Ok(self)
+    }
+}
+
 impl<K: UnifyKey> VarValue<K> {
     fn new_var(key: K, value: K::Value) -> VarValue<K> {
         VarValue::new(key, value, 0)
     }
 
-    fn new(parent: K, value: K::Value, rank: u32) -> VarValue<K> {
+    fn new(parent: K, value: K::Value, rank: u8) -> VarValue<K> {
         VarValue {
-            parent: parent, // this is a root
-            value: value,
-            rank: rank,
+            parent, // this is a root
+            value,
+            rank,
         }
     }
 
+    /// Builds a `VarValue` with the given parent index, value and
+    /// rank, for assembling a specific table state by hand (e.g., to
+    /// reproduce a bug report). The rank saturates at `u8::MAX`, as
+    /// it does in the table itself.
+    ///
+    /// Nothing is checked: a parent out of bounds, a cycle of parent
+    /// links, or a rank that does not match the tree can all produce
+    /// an invalid table, on which `find` may panic or loop. Use
+    /// `UnificationTable::validate` to check a state built this way.
+    pub fn new_for_test(parent: u32, value: K::Value, rank: u32) -> VarValue<K> {
+        let rank = cmp::min(rank, u32::from(u8::MAX)) as u8;
+        VarValue::new(K::from_index(parent), value, rank)
+    }
+
     fn redirect(&mut self, to: K) {
         self.parent = to;
     }
 
-    fn root(&mut self, rank: u32, value: K::Value) {
+    fn root(&mut self, rank: u8, value: K::Value) {
         self.rank = rank;
         self.value = value;
     }
@@ -242,6 +663,123 @@ impl<S: UnificationStore> UnificationTable<S> {
         Self::default()
     }
 
+    /// Creates a table that records, for debugging, every operation
+    /// that creates keys, unions them or changes their values (see
+    /// `Op`). The log can be retrieved with `take_log` and replayed
+    /// with `replay`. The recorded operations are:
+    ///
+    /// - `new_key` and `new_key_with`;
+    /// - `unify_var_var` and `unify_var_value`, including the calls
+    ///   made by `union`, `union_value`, `unify_while`,
+    ///   `unify_var_with_value_of`, `Entry` and the `_tagged` variants;
+    /// - `unify_var_value_checked` and `unify_var_value_interned`;
+    /// - `probe_or_set` and `unset_value`, as `SetValue`;
+    /// - `unify_var_var_or`, `unify_var_var_collecting` and
+    ///   `unify_var_value_or`, by their effect: a union or value that
+    ///   merged cleanly is recorded as such, while a conflict is
+    ///   recorded as `SetValue`s of the substituted value (followed,
+    ///   for two keys, by the union). Replaying that union assumes
+    ///   that a value can be merged with itself.
+    ///
+    /// Snapshots, rollbacks, `reset_unifications`, `reset_values`,
+    /// `restore_value` and `compact` are not recorded, so a replayed
+    /// log only reproduces the table if those are not used.
+    pub fn with_recording() -> Self {
+        UnificationTable {
+            log: Some(Vec::new()),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the function used to pick the new root when two roots of
+    /// equal rank are unioned. It is given both roots and must return
+    /// one of them. It only breaks ties: it is not consulted if
+    /// `UnifyKey::order_roots` picks a root, nor if one root has a
+    /// greater rank, since that root always wins. By default, the
+    /// root of the second key passed to `unify_var_var` wins; this
+    /// lets you fix the choice explicitly, which matters if you rely
+    /// on which key `find` returns, or if your `unify_values` is not
+    /// symmetric in its side effects. `prefer` may be a closure that
+    /// captures state; clones of the table share it.
+    pub fn prefer<F>(mut self, prefer: F) -> Self
+    where
+        F: Fn(S::Key, S::Key) -> S::Key + Send + Sync + 'static,
+    {
+        self.prefer = Some(PreferRoot(Arc::new(prefer)));
+        self
+    }
+
+    /// Registers `hook` to be called, with the new number of keys,
+    /// whenever creating a key brings the number of keys to a multiple
+    /// of `threshold`. This replaces any hook registered earlier. When
+    /// no hook is registered, creating a key costs a single check.
+    ///
+    /// The hook must be `Send` and `Sync` so that the table still is.
+    /// It cannot be cloned, so a clone of the table has no hook.
+    pub fn on_growth<F>(&mut self, threshold: usize, hook: F)
+    where
+        F: FnMut(usize) + Send + Sync + 'static,
+    {
+        assert!(threshold > 0, "growth threshold must be positive");
+        self.growth = Some(GrowthHook { threshold, hook: Box::new(hook) });
+    }
+
+    /// Calls the growth hook, if any, after a key has been created.
+    #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+    fn grew(&mut self) {
+        let len = self.len();
+        if let Some(ref mut growth) = self.growth {
+            if len % growth.threshold == 0 {
+                (growth.hook)(len);
+            }
+        }
+    }
+
+    /// Makes the table collect statistics about the length of the
+    /// paths it walks to find root keys, which can be retrieved with
+    /// `find_stats`. This is diagnostic instrumentation; it makes
+    /// every lookup walk its path twice.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(FindStats::default());
+        self
+    }
+
+    /// Sets what `try_find`, `try_probe_value` and `try_unify_var_var`
+    /// do when given a key that is out of bounds (see `OnOob`).
+    pub fn with_bounds_check(mut self, policy: OnOob) -> Self {
+        self.bounds_check = policy;
+        self
+    }
+
+    /// Returns the statistics collected so far (all zero unless
+    /// `with_stats` was used).
+    pub fn find_stats(&self) -> FindStats {
+        self.stats.unwrap_or_default()
+    }
+
+    /// Resets the statistics collected so far, e.g. between phases.
+    pub fn reset_stats(&mut self) {
+        if let Some(ref mut stats) = self.stats {
+            *stats = FindStats::default();
+        }
+    }
+
+    /// Returns the operations recorded since the table was created
+    /// (or since the last call to `take_log`). Empty unless the table
+    /// was created with `with_recording`.
+    pub fn take_log(&mut self) -> Vec<Op<S::Key>> {
+        match self.log {
+            Some(ref mut log) => mem::take(log),
+            None => Vec::new(),
+        }
+    }
+
+    fn record(&mut self, op: impl FnOnce() -> Op<S::Key>) {
+        if let Some(ref mut log) = self.log {
+            log.push(op());
+        }
+    }
+
     /// Starts a new snapshot. Each snapshot must be either
     /// rolled back or committed in a "LIFO" (stack) order.
     pub fn snapshot(&mut self) -> Snapshot<S> {
@@ -265,12 +803,61 @@ impl<S: UnificationStore> UnificationTable<S> {
         self.values.commit(snapshot.snapshot);
     }
 
+    /// Iterates over the keys that were created since `snapshot` was
+    /// started, along with their current `VarValue`s, in index order.
+    /// Keys that already existed but were modified within the
+    /// snapshot are not included.
+    pub fn values_since_snapshot_iter<'a>(
+        &'a self,
+        snapshot: &Snapshot<S>,
+    ) -> impl Iterator<Item = (S::Key, &'a VarValue<S::Key>)> + 'a {
+        self.values
+            .values_since_snapshot(&snapshot.snapshot)
+            .map(move |i| (S::Key::from_index(i as u32), &self.values[i]))
+    }
+
+    /// Runs `op` inside a snapshot: if it returns `Ok`, the snapshot
+    /// is committed, and if it returns `Err`, the snapshot is rolled
+    /// back, undoing whatever `op` did. Since each call starts and
+    /// ends its own snapshot, calls may be nested.
+    pub fn in_snapshot<R, E>(&mut self, op: impl FnOnce(&mut Self) -> Result<R, E>) -> Result<R, E> {
+        let snapshot = self.snapshot();
+        let result = op(self);
+        match result {
+            Ok(_) => self.commit(snapshot),
+            Err(_) => self.rollback_to(snapshot),
+        }
+        result
+    }
+
     /// Creates a fresh key with the given value.
     pub fn new_key(&mut self, value: S::Value) -> S::Key {
+        self.record(|| Op::NewKey(value.clone()));
         let len = self.values.len();
         let key: S::Key = UnifyKey::from_index(len as u32);
         self.values.push(VarValue::new_var(key, value));
         debug!("{}: created new key: {:?}", S::tag(), key);
+        self.grew();
+        key
+    }
+
+    /// Creates a fresh key with the given value and rank, rather than
+    /// the rank of 0 that `new_key` uses. This is meant for rebuilding
+    /// a table whose ranks are already known, e.g. when importing from
+    /// another solver.
+    ///
+    /// NB. Ranks that do not reflect the actual shape of the table
+    /// will not cause wrong answers, but they can make `find`
+    /// dramatically slower. Panics if `rank` is larger than 255,
+    /// which is more than any real table can reach.
+    pub fn new_key_with(&mut self, value: S::Value, rank: u32) -> S::Key {
+        assert!(rank <= u32::from(u8::MAX), "rank {} is out of range", rank);
+        self.record(|| Op::NewKeyWithRank(value.clone(), rank));
+        let len = self.values.len();
+        let key: S::Key = UnifyKey::from_index(len as u32);
+        self.values.push(VarValue::new(key, value, rank as u8));
+        debug!("{}: created new key: {:?} with rank {}", S::tag(), key, rank);
+        self.grew();
         key
     }
 
@@ -280,6 +867,12 @@ impl<S: UnificationStore> UnificationTable<S> {
         self.values.reserve(num_new_keys);
     }
 
+    /// Releases any excess capacity held by the backing store. The
+    /// keys and their values are unaffected.
+    pub fn shrink_to_fit(&mut self) {
+        self.values.shrink_to_fit();
+    }
+
     /// Clears all unifications that have been performed, resetting to
     /// the initial state. The values of each variable are given by
     /// the closure.
@@ -288,17 +881,226 @@ impl<S: UnificationStore> UnificationTable<S> {
         mut value: impl FnMut(S::Key) -> S::Value,
     ) {
         self.values.reset_unifications(|i| {
-            let key = UnifyKey::from_index(i as u32);
+            let key = UnifyKey::from_index(i);
             let value = value(key);
             VarValue::new_var(key, value)
         });
     }
 
+    /// Replaces the value of every equivalence class, keeping the
+    /// unions that have been performed. The closure is invoked once
+    /// per class, with its root key; the values of other keys are
+    /// never consulted, so they are left alone.
+    pub fn reset_values(&mut self, mut value: impl FnMut(S::Key) -> S::Value) {
+        for i in 0..self.len() {
+            let key: S::Key = UnifyKey::from_index(i as u32);
+            if self.value(key).parent(key).is_none() {
+                let value = value(key);
+                self.update_value(key, |node| node.value = value);
+            }
+        }
+    }
+
+    /// Discards every key that is not in `live`, renumbering the
+    /// remaining keys contiguously (preserving their relative order).
+    /// A live key whose root is not live is also discarded. Returns a
+    /// map from each old key index to the corresponding new key, or
+    /// `None` if the key was discarded. `live` must have room for at
+    /// least `len()` bits.
+    ///
+    /// This must not be called while a snapshot is open, since the
+    /// snapshot would refer to the old numbering, and panics if one
+    /// is; for the same reason, any recorded log (see
+    /// `with_recording`) is cleared.
+    pub fn compact(&mut self, live: &BitVector) -> Vec<Option<S::Key>> {
+        assert!(
+            !self.values.in_snapshot(),
+            "cannot compact a unification table while a snapshot is open"
+        );
+        let len = self.len();
+        let roots: Vec<S::Key> = (0..len as u32)
+            .map(|i| self.get_root_key(UnifyKey::from_index(i)))
+            .collect();
+
+        let mut map = vec![None; len];
+        let mut num_live = 0;
+        for i in 0..len {
+            if live.contains(i) && live.contains(roots[i].index() as usize) {
+                map[i] = Some(S::Key::from_index(num_live));
+                num_live += 1;
+            }
+        }
+
+        let mut values = S::default();
+        values.reserve(num_live as usize);
+        values.extend((0..len).filter(|&i| map[i].is_some()).map(|i| {
+            let parent = map[roots[i].index() as usize].unwrap();
+            let old = &self.values[i];
+            VarValue::new(parent, old.value.clone(), old.rank)
+        }));
+        self.values = values;
+
+        if let Some(ref mut log) = self.log {
+            log.clear();
+        }
+
+        map
+    }
+
+    /// Discards every equivalence class for which `keep`, given the
+    /// root and the value of the class, returns false, and renumbers
+    /// the remaining keys as `compact` does (whose map is returned).
+    /// `keep` is called once per class, so the members of a class
+    /// are discarded or kept together. The same restrictions as for
+    /// `compact` apply.
+    pub fn retain_classes<F>(&mut self, mut keep: F) -> Vec<Option<S::Key>>
+    where
+        F: FnMut(S::Key, &S::Value) -> bool,
+    {
+        let len = self.len();
+        let mut decided = BitVector::new(len);
+        let mut live = BitVector::new(len);
+        for i in 0..len {
+            let root = self.get_root_key(S::Key::from_index(i as u32));
+            let r = root.index() as usize;
+            if decided.insert(r) && keep(root, &self.value(root).value) {
+                live.insert(r);
+            }
+            if live.contains(r) {
+                live.insert(i);
+            }
+        }
+        self.compact(&live)
+    }
+
     /// Returns the number of keys created so far.
     pub fn len(&self) -> usize {
         self.values.len()
     }
 
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the parent index of each key, in index order; that
+    /// is, entry `i` corresponds to the key `UnifyKey::from_index(i)`.
+    /// Roots are their own parent. The parents are reported as they
+    /// are currently stored, without any further path compression, so
+    /// a consumer may have to walk several links to reach the root.
+    pub fn raw_parents(&self) -> Vec<u32> {
+        (0..self.len()).map(|i| self.values[i].parent.index()).collect()
+    }
+
+    /// Checks the internal consistency of the table: that every
+    /// parent link is in bounds and that following the links from any
+    /// key reaches a root (i.e., there are no cycles). Returns a
+    /// description of the first problem found. This is meant for
+    /// debugging and tests; it takes O(n) time and nothing calls it
+    /// automatically.
+    pub fn validate(&self) -> Result<(), String> {
+        let len = self.len();
+        let parents = self.raw_parents();
+        for (i, &parent) in parents.iter().enumerate() {
+            if parent as usize >= len {
+                return Err(format!(
+                    "key {} has parent {}, but the table only has {} keys",
+                    i, parent, len
+                ));
+            }
+        }
+
+        // 0 = unvisited, 1 = on the current path, 2 = reaches a root.
+        let mut state = vec![0u8; len];
+        for start in 0..len {
+            let mut path = vec![];
+            let mut i = start;
+            while state[i] == 0 {
+                state[i] = 1;
+                path.push(i);
+                let parent = parents[i] as usize;
+                if parent == i {
+                    state[i] = 2;
+                    break;
+                }
+                i = parent;
+            }
+            if state[i] == 1 {
+                return Err(format!("the parent links from key {} form a cycle", start));
+            }
+            for j in path {
+                state[j] = 2;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value stored for each key, in index order; that
+    /// is, entry `i` corresponds to the key `UnifyKey::from_index(i)`.
+    /// Only the values of roots are meaningful (see `raw_parents`).
+    pub fn raw_values(&self) -> Vec<S::Value> {
+        (0..self.len()).map(|i| self.values[i].value.clone()).collect()
+    }
+
+    /// Takes an immutable, fully path-compressed copy of the current
+    /// partition and values, which can be queried through a shared
+    /// reference (see `ReadSnapshot`). The table itself is not
+    /// modified. This copies every `VarValue`, for persistent tables
+    /// as well as in-place ones.
+    pub fn read_snapshot(&self) -> ReadSnapshot<S::Key> {
+        let values = (0..self.len())
+            .map(|i| {
+                let mut root: S::Key = UnifyKey::from_index(i as u32);
+                while let Some(parent) = self.value(root).parent(root) {
+                    root = parent;
+                }
+                let value = &self.values[i];
+                VarValue::new(root, value.value.clone(), value.rank)
+            })
+            .collect();
+        ReadSnapshot { values }
+    }
+
+    /// Creates a new in-place table with exactly the same partition
+    /// (the same parent links and ranks) but with the value of each
+    /// key mapped through `f`, for a key type `K2` whose values are
+    /// of the new type. Key `i` of this table corresponds to key `i`
+    /// of the result. `f` is applied to the stored value of every
+    /// key, not just of the roots.
+    pub fn map_values_into<K2>(&self, f: impl Fn(&S::Value) -> K2::Value) -> UnificationTable<InPlace<K2>>
+    where
+        K2: UnifyKey,
+    {
+        let mut table: UnificationTable<InPlace<K2>> = UnificationTable::new();
+        table.reserve(self.len());
+        for i in 0..self.len() {
+            let value = &self.values[i];
+            let parent = K2::from_index(value.parent.index());
+            table.values.push(VarValue::new(parent, f(&value.value), value.rank));
+        }
+        table
+    }
+
+    /// Returns a copy of the `VarValue` stored for `key` (its parent
+    /// link, rank and value), to be written back later with
+    /// `restore_value`. This is much cheaper than a full snapshot when
+    /// speculating about a single key.
+    pub fn save_value(&self, key: S::Key) -> VarValue<S::Key> {
+        self.value(key).clone()
+    }
+
+    /// Writes back a `VarValue` obtained from `save_value`. Nothing
+    /// checks that the saved parent link is still consistent with the
+    /// rest of the table: restoring a stale value, e.g. after `key`
+    /// was unioned with another key (or had another key unioned into
+    /// it), can corrupt the partition. It is safe if the key has only
+    /// been the target of value updates (such as `unify_var_value`)
+    /// since it was saved. Restores are not recorded in the log (see
+    /// `with_recording`).
+    pub fn restore_value(&mut self, key: S::Key, saved: VarValue<S::Key>) {
+        self.update_value(key, |value| *value = saved);
+    }
+
     /// Obtains the current value for a particular key.
     /// Not for end-users; they can use `probe_value`.
     fn value(&self, key: S::Key) -> &VarValue<S::Key> {
@@ -312,6 +1114,18 @@ impl<S: UnificationStore> UnificationTable<S> {
     /// NB. This is a building-block operation and you would probably
     /// prefer to call `probe` below.
     fn get_root_key(&mut self, vid: S::Key) -> S::Key {
+        if self.stats.is_some() {
+            let steps = self.path_length(vid);
+            if let Some(ref mut stats) = self.stats {
+                stats.record(steps);
+            }
+        }
+
+        self.compress_root_key(vid)
+    }
+
+    /// The actual work of `get_root_key`.
+    fn compress_root_key(&mut self, vid: S::Key) -> S::Key {
         let redirect = {
             match self.value(vid).parent(vid) {
                 None => return vid,
@@ -319,7 +1133,7 @@ impl<S: UnificationStore> UnificationTable<S> {
             }
         };
 
-        let root_key: S::Key = self.get_root_key(redirect);
+        let root_key: S::Key = self.compress_root_key(redirect);
         if root_key != redirect {
             // Path compression
             self.update_value(vid, |value| value.parent = root_key);
@@ -328,6 +1142,16 @@ impl<S: UnificationStore> UnificationTable<S> {
         root_key
     }
 
+    /// Number of parent links between `vid` and its root.
+    fn path_length(&self, mut vid: S::Key) -> u64 {
+        let mut steps = 0;
+        while let Some(parent) = self.value(vid).parent(vid) {
+            vid = parent;
+            steps += 1;
+        }
+        steps
+    }
+
     fn update_value<OP>(&mut self, key: S::Key, op: OP)
     where
         OP: FnOnce(&mut VarValue<S::Key>),
@@ -363,7 +1187,7 @@ impl<S: UnificationStore> UnificationTable<S> {
                 if rank_a > rank_b {
                     rank_a
                 } else {
-                    rank_b + 1
+                    rank_b.saturating_add(1)
                 }
             } else {
                 debug_assert!(new_root == key_b);
@@ -371,7 +1195,7 @@ impl<S: UnificationStore> UnificationTable<S> {
                 if rank_b > rank_a {
                     rank_b
                 } else {
-                    rank_a + 1
+                    rank_a.saturating_add(1)
                 }
             };
             self.redirect_root(new_rank, redirected, new_root, new_value);
@@ -385,7 +1209,15 @@ impl<S: UnificationStore> UnificationTable<S> {
         } else {
             // If equal, redirect one to the other and increment the
             // other's rank.
-            self.redirect_root(rank_a + 1, key_a, key_b, new_value);
+            let new_rank = rank_a.saturating_add(1);
+            match self.prefer {
+                Some(ref prefer) if (prefer.0)(key_a, key_b) == key_a => {
+                    self.redirect_root(new_rank, key_b, key_a, new_value);
+                }
+                _ => {
+                    self.redirect_root(new_rank, key_a, key_b, new_value);
+                }
+            }
         }
     }
 
@@ -395,7 +1227,7 @@ impl<S: UnificationStore> UnificationTable<S> {
     /// `new_rank` and `new_value` respectively.
     fn redirect_root(
         &mut self,
-        new_rank: u32,
+        new_rank: u8,
         old_root_key: S::Key,
         new_root_key: S::Key,
         new_value: S::Value,
@@ -409,15 +1241,61 @@ impl<S: UnificationStore> UnificationTable<S> {
     }
 }
 
-/// ////////////////////////////////////////////////////////////////////////
-/// Public API
+// ////////////////////////////////////////////////////////////////////////
+// Public API
 
-impl<'tcx, S, K, V> UnificationTable<S>
+impl<S, K, V> UnificationTable<S>
 where
     S: UnificationStore<Key = K, Value = V>,
     K: UnifyKey<Value = V>,
     V: UnifyValue,
 {
+    /// Creates a new table by performing the given operations, as
+    /// recorded by a table created with `with_recording`. Errors
+    /// from unification are ignored, just as they would have been
+    /// reported (and not applied) in the original table.
+    pub fn replay(log: &[Op<K>]) -> Self {
+        let mut table = Self::new();
+        for op in log {
+            match *op {
+                Op::NewKey(ref value) => {
+                    table.new_key(value.clone());
+                }
+                Op::NewKeyWithRank(ref value, rank) => {
+                    table.new_key_with(value.clone(), rank);
+                }
+                Op::UnifyVarVar(a_id, b_id) => {
+                    let _ = table.unify_var_var(a_id, b_id);
+                }
+                Op::UnifyVarValue(a_id, ref value) => {
+                    let _ = table.unify_var_value(a_id, value.clone());
+                }
+                Op::SetValue(a_id, ref value) => {
+                    table.set_value(a_id, value.clone());
+                }
+            }
+        }
+        table
+    }
+
+    /// Creates a new table reflecting the given partition. One key is
+    /// created for each value, in order, so the keys for the first
+    /// group come first, then those for the second group, and so on.
+    /// The keys in each group are then unioned together, combining
+    /// their values with `unify_values`; if that fails for any group,
+    /// the error is returned.
+    pub fn from_partition(groups: &[&[V]]) -> Result<Self, V::Error> {
+        let mut table = Self::new();
+        table.reserve(groups.iter().map(|group| group.len()).sum());
+        for group in groups {
+            let keys: Vec<K> = group.iter().map(|value| table.new_key(value.clone())).collect();
+            for pair in keys.windows(2) {
+                table.unify_var_var(pair[0], pair[1])?;
+            }
+        }
+        Ok(table)
+    }
+
     /// Unions two keys without the possibility of failure; only
     /// applicable when unify values use `NoError` as their error
     /// type.
@@ -441,6 +1319,14 @@ where
         self.unify_var_value(id, value).unwrap();
     }
 
+    /// Returns an `Entry` for operating repeatedly on `id`.
+    pub fn entry<K1>(&mut self, id: K1) -> Entry<'_, S>
+    where
+        K1: Into<K>,
+    {
+        Entry { key: id.into(), table: self }
+    }
+
     /// Given two keys, indicates whether they have been unioned together.
     pub fn unioned<K1, K2>(&mut self, a_id: K1, b_id: K2) -> bool
     where
@@ -450,6 +1336,22 @@ where
         self.find(a_id) == self.find(b_id)
     }
 
+    /// Checks whether any two of the given keys have already been
+    /// unioned together. If so, returns the first such pair `(a, b)`:
+    /// `b` is the first key in the slice that shares a root with some
+    /// earlier key, and `a` is that earlier key.
+    pub fn any_unioned(&mut self, ids: &[K]) -> Option<(K, K)> {
+        let mut seen = HashMap::new();
+        for &id in ids {
+            let root = self.find(id);
+            if let Some(&earlier) = seen.get(&root.index()) {
+                return Some((earlier, id));
+            }
+            seen.insert(root.index(), id);
+        }
+        None
+    }
+
     /// Given a key, returns the (current) root key.
     pub fn find<K1>(&mut self, id: K1) -> K
     where
@@ -459,6 +1361,157 @@ where
         self.get_root_key(id)
     }
 
+    /// Like `find`, but applies the table's `OnOob` policy if `id` is
+    /// out of bounds.
+    pub fn try_find<K1>(&mut self, id: K1) -> Result<K, OutOfBounds<K>>
+    where
+        K1: Into<K>,
+    {
+        let id = self.check_bounds(id.into())?;
+        Ok(self.find(id))
+    }
+
+    // Returns `id`, or what to use in its place if it is out of
+    // bounds, according to the `OnOob` policy.
+    fn check_bounds(&self, id: K) -> Result<K, OutOfBounds<K>> {
+        let len = self.len();
+        if (id.index() as usize) < len {
+            return Ok(id);
+        }
+        match self.bounds_check {
+            OnOob::Saturate if len > 0 => Ok(K::from_index(len as u32 - 1)),
+            OnOob::Error => Err(OutOfBounds(id)),
+            OnOob::Panic | OnOob::Saturate => {
+                panic!("key {:?} is out of bounds (the table has {} keys)", id, len)
+            }
+        }
+    }
+
+    /// Indicates whether `id` is currently the root of its equivalence
+    /// class. Unlike `find`, this takes `&self` and so does no path
+    /// compression; it is a single read of the key's parent link.
+    pub fn is_root<K1>(&self, id: K1) -> bool
+    where
+        K1: Into<K>,
+    {
+        let id = id.into();
+        self.value(id).parent(id).is_none()
+    }
+
+    /// Like `find`, but takes `&self`: walks the parent links to the
+    /// root without compressing them. Since nothing is written, this
+    /// can be used to check that compression preserves roots. Panics
+    /// if the parent links form a cycle, which would indicate a
+    /// corrupted table.
+    pub fn find_without_compression<K1>(&self, id: K1) -> K
+    where
+        K1: Into<K>,
+    {
+        let mut key = id.into();
+        for _ in 0..=self.len() {
+            match self.value(key).parent(key) {
+                None => return key,
+                Some(parent) => key = parent,
+            }
+        }
+        panic!("cycle in the parent links of {:?}", key);
+    }
+
+    /// Returns the number of keys in the equivalence class of `id`
+    /// (including `id` itself). The table does not track class sizes,
+    /// so this finds the root of every key, which takes O(n) time.
+    pub fn class_size<K1>(&mut self, id: K1) -> usize
+    where
+        K1: Into<K>,
+    {
+        let root = self.find(id);
+        (0..self.len() as u32)
+            .filter(|&i| self.find(K::from_index(i)) == root)
+            .count()
+    }
+
+    /// Returns a map from each class size to the number of classes of
+    /// that size, covering every class in the table (including
+    /// singletons, of size 1). The sizes of all the classes thus add
+    /// up to `len()`.
+    pub fn class_size_histogram(&mut self) -> BTreeMap<usize, usize> {
+        let mut sizes = HashMap::new();
+        for i in 0..self.len() as u32 {
+            let root = self.find(K::from_index(i));
+            *sizes.entry(root.index()).or_insert(0) += 1;
+        }
+
+        let mut histogram = BTreeMap::new();
+        for (_, size) in sizes {
+            *histogram.entry(size).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Returns the number of distinct values held by the equivalence
+    /// classes of the table (each class counting once, whatever its
+    /// size). For example, if `Option` values are used, `None`
+    /// counts as one value however many classes are unresolved.
+    pub fn num_distinct_values(&mut self) -> usize
+    where
+        V: Eq + Hash,
+    {
+        let mut values = HashSet::new();
+        for i in 0..self.len() as u32 {
+            let key = K::from_index(i);
+            if self.find(key) == key {
+                values.insert(self.value(key).value.clone());
+            }
+        }
+        values.len()
+    }
+
+    /// Maps each of the given keys to its root key and returns the
+    /// distinct roots, in the order in which they were first seen.
+    /// A result of length one thus indicates that all the keys have
+    /// been unioned together.
+    pub fn roots_of<I>(&mut self, ids: I) -> Vec<K>
+    where
+        I: IntoIterator,
+        I::Item: Into<K>,
+    {
+        let mut seen = HashSet::new();
+        let mut roots = Vec::new();
+        for id in ids {
+            let root = self.find(id);
+            if seen.insert(root.index()) {
+                roots.push(root);
+            }
+        }
+        roots
+    }
+
+    /// Compares the partition of this table with that of `other`,
+    /// returning the unions present here but missing there: for each
+    /// key that is not its own root in `self`, the pair `(root, key)`
+    /// is included if the two keys are not unioned in `other`. The
+    /// pairs come in key order. Swap the tables to get the opposite
+    /// difference; both are empty iff the partitions are equal.
+    ///
+    /// Both tables are path-compressed along the way. Panics if they
+    /// do not have the same number of keys.
+    pub fn partition_diff(&mut self, other: &mut Self) -> Vec<(K, K)> {
+        assert_eq!(
+            self.len(),
+            other.len(),
+            "cannot diff partitions of tables with different numbers of keys"
+        );
+        let mut diff = Vec::new();
+        for i in 0..self.len() as u32 {
+            let key = K::from_index(i);
+            let root = self.find(key);
+            if root != key && !other.unioned(root, key) {
+                diff.push((root, key));
+            }
+        }
+        diff
+    }
+
     /// Unions together two variables, merging their values. If
     /// merging the values fails, the error is propagated and this
     /// method has no effect.
@@ -469,6 +1522,7 @@ where
     {
         let a_id = a_id.into();
         let b_id = b_id.into();
+        self.record(|| Op::UnifyVarVar(a_id, b_id));
 
         let root_a = self.get_root_key(a_id);
         let root_b = self.get_root_key(b_id);
@@ -477,9 +1531,52 @@ where
             return Ok(());
         }
 
-        let combined = V::unify_values(&self.value(root_a).value, &self.value(root_b).value)?;
+        let combined = self.unify_root_values(root_a, root_b)?;
+        self.unify_roots(root_a, root_b, combined);
+        Ok(())
+    }
+
+    /// Like `unify_var_var`, but applies the table's `OnOob` policy if
+    /// either key is out of bounds. Both keys are checked before
+    /// anything is unified, so an `OutOfBounds` error leaves the
+    /// table unchanged; otherwise, the inner result is that of
+    /// `unify_var_var`.
+    pub fn try_unify_var_var<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+    ) -> Result<Result<(), V::Error>, OutOfBounds<K>>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let a_id = self.check_bounds(a_id.into())?;
+        let b_id = self.check_bounds(b_id.into())?;
+        Ok(self.unify_var_var(a_id, b_id))
+    }
 
-        Ok(self.unify_roots(root_a, root_b, combined))
+    /// Repeatedly invokes `next` to obtain pairs of keys and unions
+    /// them together, until `next` returns `None`. Returns the number
+    /// of pairs that were not already unioned, i.e., the number of
+    /// merges performed. If merging the values of some pair fails,
+    /// the error is propagated immediately; pairs before it remain
+    /// unioned.
+    pub fn unify_while<K1, K2, F>(&mut self, mut next: F) -> Result<usize, V::Error>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+        F: FnMut() -> Option<(K1, K2)>,
+    {
+        let mut merges = 0;
+        while let Some((a_id, b_id)) = next() {
+            let a_id = a_id.into();
+            let b_id = b_id.into();
+            if !self.unioned(a_id, b_id) {
+                self.unify_var_var(a_id, b_id)?;
+                merges += 1;
+            }
+        }
+        Ok(merges)
     }
 
     /// Sets the value of the key `a_id` to `b`, attempting to merge
@@ -489,12 +1586,211 @@ where
         K1: Into<K>,
     {
         let a_id = a_id.into();
+        self.record(|| Op::UnifyVarValue(a_id, b.clone()));
         let root_a = self.get_root_key(a_id);
         let value = V::unify_values(&self.value(root_a).value, &b)?;
         self.update_value(root_a, |node| node.value = value);
         Ok(())
     }
 
+    /// Like `unify_var_value`, but reports whether the value of the
+    /// class changed: `Ok(false)` means that it already was the
+    /// result of the unification. On failure, the error carries the
+    /// key, and the class is left unchanged.
+    pub fn unify_var_value_checked<K1>(&mut self, a_id: K1, b: V) -> Result<bool, UnifyError<K, V::Error>>
+    where
+        K1: Into<K>,
+        V: PartialEq,
+    {
+        let a_id = a_id.into();
+        self.record(|| Op::UnifyVarValue(a_id, b.clone()));
+        let root_a = self.get_root_key(a_id);
+        let value = V::unify_values(&self.value(root_a).value, &b)
+            .map_err(|error| UnifyError { key: a_id, error })?;
+        if value == self.value(root_a).value {
+            return Ok(false);
+        }
+        self.update_value(root_a, |node| node.value = value);
+        Ok(true)
+    }
+
+    /// Like `unify_var_value`, but the resulting value is interned in
+    /// `interner`: if an equal value was interned before, a clone of
+    /// that value is stored instead, and `probe_value` then returns
+    /// clones of it. This only saves memory if cloning shares the
+    /// underlying data, as it does for `Rc` and `Arc`, which are the
+    /// intended value types.
+    pub fn unify_var_value_interned<K1>(
+        &mut self,
+        a_id: K1,
+        b: V,
+        interner: &mut Interner<V>,
+    ) -> Result<(), V::Error>
+    where
+        K1: Into<K>,
+        V: Eq + Hash,
+    {
+        let a_id = a_id.into();
+        self.record(|| Op::UnifyVarValue(a_id, b.clone()));
+        let root_a = self.get_root_key(a_id);
+        let value = V::unify_values(&self.value(root_a).value, &b)?;
+        let value = interner.intern(value);
+        self.update_value(root_a, |node| node.value = value);
+        Ok(())
+    }
+
+    /// Like `unify_var_var`, but the error is tagged with the tag of
+    /// the key type.
+    pub fn unify_var_var_tagged<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+    ) -> Result<(), TaggedError<V::Error>>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        self.unify_var_var(a_id, b_id).map_err(|error| TaggedError {
+            tag: K::tag(),
+            error,
+        })
+    }
+
+    /// Like `unify_var_value`, but the error is tagged with the tag of
+    /// the key type.
+    pub fn unify_var_value_tagged<K1>(&mut self, a_id: K1, b: V) -> Result<(), TaggedError<V::Error>>
+    where
+        K1: Into<K>,
+    {
+        self.unify_var_value(a_id, b).map_err(|error| TaggedError {
+            tag: K::tag(),
+            error,
+        })
+    }
+
+    /// Merges the current value of `source` into the value of
+    /// `target`, as if by `unify_var_value(target, probe_value(source))`.
+    /// Unlike `unify_var_var`, this does not union the two keys: they
+    /// remain in separate equivalence classes afterwards (unless they
+    /// were already unioned), and later changes to one do not affect
+    /// the other.
+    pub fn unify_var_with_value_of<K1, K2>(&mut self, target: K1, source: K2) -> Result<(), V::Error>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let value = self.probe_value(source);
+        self.unify_var_value(target, value)
+    }
+
+    /// Like `unify_var_var`, but never fails: if merging the values
+    /// fails, `on_conflict` is invoked with the error and the value
+    /// it returns is used for the combined class instead. This is
+    /// useful in error-recovery mode, where you would rather
+    /// substitute some "error" value (e.g., a top or bottom element
+    /// of your lattice) than stop inference.
+    pub fn unify_var_var_or<K1, K2, F>(&mut self, a_id: K1, b_id: K2, on_conflict: F)
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+        F: FnOnce(V::Error) -> V,
+    {
+        self.unify_var_var_recovering(a_id.into(), b_id.into(), |_, _, error| on_conflict(error));
+    }
+
+    /// Like `unify_var_var`, but never fails: if merging the values
+    /// fails, the keys and the error are pushed onto `conflicts` and
+    /// the classes are unioned anyway, keeping the value of `a_id`'s
+    /// class. This lets a solver report every conflict rather than
+    /// stopping at the first.
+    ///
+    /// The conflicts are collected in a `Vec` owned by the caller,
+    /// rather than in a mode of the table drained by a
+    /// `take_conflicts` method, because storing them in the table
+    /// would require `UnifyValue::Error` to be `Clone` and `Debug`
+    /// for the table to remain so.
+    pub fn unify_var_var_collecting<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+        conflicts: &mut Vec<Conflict<K, V::Error>>,
+    ) where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let a = a_id.into();
+        let b = b_id.into();
+        self.unify_var_var_recovering(a, b, |this, root_a, error| {
+            conflicts.push(Conflict { a, b, error });
+            this.value(root_a).value.clone()
+        });
+    }
+
+    /// Unions the classes of `a_id` and `b_id`; if merging their
+    /// values fails, `on_conflict` is given the table, the root of
+    /// `a_id` and the error, and returns the value to keep.
+    fn unify_var_var_recovering<F>(&mut self, a_id: K, b_id: K, on_conflict: F)
+    where
+        F: FnOnce(&Self, K, V::Error) -> V,
+    {
+        let root_a = self.get_root_key(a_id);
+        let root_b = self.get_root_key(b_id);
+
+        if root_a == root_b {
+            return;
+        }
+
+        match self.unify_root_values(root_a, root_b) {
+            Ok(combined) => {
+                self.record(|| Op::UnifyVarVar(a_id, b_id));
+                self.unify_roots(root_a, root_b, combined);
+            }
+            Err(error) => {
+                let combined = on_conflict(self, root_a, error);
+                // Replaying a plain `UnifyVarVar` would fail again, so
+                // give both classes the substituted value first.
+                self.record(|| Op::SetValue(a_id, combined.clone()));
+                self.record(|| Op::SetValue(b_id, combined.clone()));
+                self.record(|| Op::UnifyVarVar(a_id, b_id));
+                self.unify_roots(root_a, root_b, combined);
+            }
+        }
+    }
+
+    /// Like `unify_var_value`, but never fails: if merging the values
+    /// fails, `on_conflict` is invoked with the error and the value
+    /// it returns is stored instead.
+    pub fn unify_var_value_or<K1, F>(&mut self, a_id: K1, b: V, on_conflict: F)
+    where
+        K1: Into<K>,
+        F: FnOnce(V::Error) -> V,
+    {
+        let a_id = a_id.into();
+        let root_a = self.get_root_key(a_id);
+        match V::unify_values(&self.value(root_a).value, &b) {
+            Ok(value) => {
+                self.record(|| Op::UnifyVarValue(a_id, b));
+                self.update_value(root_a, |node| node.value = value);
+            }
+            Err(error) => {
+                let value = on_conflict(error);
+                self.record(|| Op::SetValue(a_id, value.clone()));
+                self.update_value(root_a, |node| node.value = value);
+            }
+        }
+    }
+
+    /// Combines the values of two distinct roots, as for unioning them.
+    fn unify_root_values(&self, root_a: K, root_b: K) -> Result<V, V::Error> {
+        let a = self.value(root_a);
+        let b = self.value(root_b);
+        let ctx = UnifyCtx {
+            rank1: u32::from(a.rank),
+            rank2: u32::from(b.rank),
+        };
+        V::unify_values_ctx(&a.value, &b.value, ctx)
+    }
+
     /// Returns the current value for the given key. If the key has
     /// been union'd, this will give the value from the current root.
     pub fn probe_value<K1>(&mut self, id: K1) -> V
@@ -505,8 +1801,108 @@ where
         let id = self.get_root_key(id);
         self.value(id).value.clone()
     }
+
+    /// Like `probe_value`, but applies the table's `OnOob` policy if
+    /// `id` is out of bounds.
+    pub fn try_probe_value<K1>(&mut self, id: K1) -> Result<V, OutOfBounds<K>>
+    where
+        K1: Into<K>,
+    {
+        let id = self.check_bounds(id.into())?;
+        Ok(self.probe_value(id))
+    }
+
+    /// Indicates whether the class containing `id` has a value other
+    /// than `V::default()`, i.e., whether it has been constrained (for
+    /// `Option` values, whether it is `Some`).
+    pub fn has_value<K1>(&mut self, id: K1) -> bool
+    where
+        K1: Into<K>,
+        V: Default + PartialEq,
+    {
+        let root = self.find(id);
+        self.value(root).value != V::default()
+    }
+
+    /// Returns the value of the class containing `id` if it differs
+    /// from `V::default()`. Otherwise, stores `default()` as the value
+    /// of the class and returns it. The new value is stored as is,
+    /// without going through `unify_values`, so this cannot fail; but
+    /// if the class is later unified with another class that has a
+    /// value of its own, the two are combined with `unify_values`
+    /// like any other values, which may fail.
+    pub fn probe_or_set<K1, F>(&mut self, id: K1, default: F) -> V
+    where
+        K1: Into<K>,
+        F: FnOnce() -> V,
+        V: Default + PartialEq,
+    {
+        let id = id.into();
+        let root = self.get_root_key(id);
+        let value = self.value(root).value.clone();
+        if value != V::default() {
+            return value;
+        }
+
+        let value = default();
+        self.set_value(id, value.clone());
+        value
+    }
+
+    /// Resets the value of the class containing `id` to
+    /// `V::default()`, retracting any value given to it by
+    /// `unify_var_value` (or by unifying with other classes) while
+    /// keeping the keys unified. If the class is later unified with
+    /// a class that has a value, the value is recomputed from
+    /// scratch by `unify_values`, as if it had never been set.
+    pub fn unset_value<K1>(&mut self, id: K1)
+    where
+        K1: Into<K>,
+        V: Default,
+    {
+        self.set_value(id.into(), V::default());
+    }
+
+    /// Overwrites the value of the class containing `id`, without
+    /// going through `unify_values`.
+    fn set_value(&mut self, id: K, value: V) {
+        self.record(|| Op::SetValue(id, value.clone()));
+        let root = self.get_root_key(id);
+        self.update_value(root, |node| node.value = value);
+    }
+}
+
+impl<S, K> UnificationTable<S>
+where
+    S: UnificationStore<Key = K, Value = ()>,
+    K: UnifyKey<Value = ()>,
+{
+    /// Creates a fresh key in a table whose keys carry no value,
+    /// i.e., where the value type is `()`.
+    pub fn new_unit(&mut self) -> K {
+        self.new_key(())
+    }
 }
 
+/// Unions each pair of keys, as with `union`. This is only available
+/// when unification cannot fail (the error type is `NoError`), since
+/// `extend` has no way to report an error; use `unify_var_var` (or
+/// `unify_while`) otherwise.
+impl<S, K, V> Extend<(K, K)> for UnificationTable<S>
+where
+    S: UnificationStore<Key = K, Value = V>,
+    K: UnifyKey<Value = V>,
+    V: UnifyValue<Error = NoError>,
+{
+    fn extend<I>(&mut self, pairs: I)
+    where
+        I: IntoIterator<Item = (K, K)>,
+    {
+        for (a_id, b_id) in pairs {
+            self.union(a_id, b_id);
+        }
+    }
+}
 
 ///////////////////////////////////////////////////////////////////////////
 
@@ -523,10 +1919,10 @@ impl<V: UnifyValue> UnifyValue for Option<V> {
 
     fn unify_values(a: &Option<V>, b: &Option<V>) -> Result<Self, V::Error> {
         match (a, b) {
-            (&None, &None) => Ok(None),
-            (&Some(ref v), &None) |
-            (&None, &Some(ref v)) => Ok(Some(v.clone())),
-            (&Some(ref a), &Some(ref b)) => {
+            (None, None) => Ok(None),
+            (Some(v), None) |
+            (None, Some(v)) => Ok(Some(v.clone())),
+            (Some(a), Some(b)) => {
                 match V::unify_values(a, b) {
                     Ok(v) => Ok(Some(v)),
                     Err(err) => Err(err),
@@ -534,4 +1930,11 @@ impl<V: UnifyValue> UnifyValue for Option<V> {
             }
         }
     }
+
+    fn unify_values_ctx(a: &Option<V>, b: &Option<V>, ctx: UnifyCtx) -> Result<Self, V::Error> {
+        match (a, b) {
+            (Some(a), Some(b)) => V::unify_values_ctx(a, b, ctx).map(Some),
+            _ => Self::unify_values(a, b),
+        }
+    }
 }
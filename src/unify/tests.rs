@@ -16,9 +16,14 @@
 extern crate test;
 #[cfg(feature = "bench")]
 use self::test::Bencher;
+use bitvec::BitVector;
 use std::cmp;
-use unify::{NoError, InPlace, InPlaceUnificationTable, UnifyKey, EqUnifyValue, UnifyValue};
-use unify::{UnificationStore, UnificationTable};
+use std::mem;
+use std::panic;
+use std::rc::Rc;
+use unify::{NoError, InPlace, UnifyKey, EqUnifyValue, Mismatch, TaggedError, UnifyCtx, UnifyValue};
+use unify::{Conflict, FindStats, OnOob, Op, OutOfBounds, UnificationStore, UnificationTable};
+use unify::{Interner, UnifyError, VarValue};
 #[cfg(feature = "persistent")]
 use unify::Persistent;
 
@@ -58,9 +63,9 @@ fn basic() {
             let mut ut: UnificationTable<S> = UnificationTable::new();
             let k1 = ut.new_key(());
             let k2 = ut.new_key(());
-            assert_eq!(ut.unioned(k1, k2), false);
+            assert!(!ut.unioned(k1, k2));
             ut.union(k1, k2);
-            assert_eq!(ut.unioned(k1, k2), true);
+            assert!(ut.unioned(k1, k2));
         }
     }
 }
@@ -414,7 +419,7 @@ fn ordered_key() {
             ut.union(k0_5, k0_6); // rank of new root now 1
 
             ut.union(k0_1, k0_5); // new root rank 2, should not be k0_5 or k0_6
-            assert!(vec![k0_1, k0_2, k0_3, k0_4].contains(&ut.find(k0_1)));
+            assert!([k0_1, k0_2, k0_3, k0_4].contains(&ut.find(k0_1)));
         }
     }
 }
@@ -422,8 +427,8 @@ fn ordered_key() {
 #[test]
 fn ordered_key_k1() {
     all_modes! {
-        S for UnitKey => {
-            let mut ut: InPlaceUnificationTable<OrderedKey> = UnificationTable::new();
+        S for OrderedKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
 
             let k0_1 = ut.new_key(OrderedRank(0));
             let k0_2 = ut.new_key(OrderedRank(0));
@@ -440,7 +445,7 @@ fn ordered_key_k1() {
 
             ut.union(k0_1, k1_5); // even though k1 has lower rank, it wins
             assert!(
-                vec![k1_5, k1_6].contains(&ut.find(k0_1)),
+                [k1_5, k1_6].contains(&ut.find(k0_1)),
                 "unexpected choice for root: {:?}",
                 ut.find(k0_1)
             );
@@ -474,3 +479,1518 @@ fn clone_table() {
         }
     }
 }
+
+#[test]
+fn unify_var_var_or_recovers() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(Some(22));
+            let k2 = ut.new_key(Some(23));
+            let k3 = ut.new_key(Some(22));
+            ut.unify_var_var_or(k1, k2, |err| {
+                assert_eq!(err, Mismatch { expected: 22, found: 23 });
+                None
+            });
+            assert!(ut.unioned(k1, k2));
+            assert_eq!(ut.probe_value(k1), None);
+
+            // No conflict: the fallback is not used.
+            ut.unify_var_var_or(k1, k3, |_| panic!("unexpected conflict"));
+            assert_eq!(ut.probe_value(k2), Some(22));
+        }
+    }
+}
+
+#[test]
+fn unify_var_value_or_recovers() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(Some(22));
+            ut.unify_var_value_or(k1, Some(23), |_| Some(-1));
+            assert_eq!(ut.probe_value(k1), Some(-1));
+
+            // The strict variant still fails.
+            assert!(ut.unify_var_value(k1, Some(23)).is_err());
+        }
+    }
+}
+
+#[test]
+fn raw_parents_and_values() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(None);
+            let k1 = ut.new_key(Some(22));
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(None);
+            ut.unify_var_var(k0, k1).unwrap(); // k0 -> k1
+            ut.unify_var_var(k2, k3).unwrap(); // k2 -> k3
+            ut.unify_var_var(k3, k1).unwrap(); // k3 -> k1, k2 not compressed
+            assert_eq!(ut.raw_parents(), vec![1, 1, 3, 1]);
+
+            let values = ut.raw_values();
+            assert_eq!(values.len(), 4);
+            assert_eq!(values[1], Some(22));
+        }
+    }
+}
+
+#[test]
+fn roots_of() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            ut.union(k1, k3);
+
+            let roots = ut.roots_of(vec![k2, k1, k3, k0, k2]);
+            assert_eq!(roots, vec![k2, ut.find(k1), k0]);
+
+            ut.union(k0, k2);
+            ut.union(k0, k1);
+            assert_eq!(ut.roots_of(vec![k0, k1, k2, k3]).len(), 1);
+        }
+    }
+}
+
+#[test]
+fn unit_value_is_free() {
+    // A `()` value should not take up any room in the table.
+    assert_eq!(mem::size_of::<VarValue<UnitKey>>(), mem::size_of::<(UnitKey, u8)>());
+
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_unit();
+            let k2 = ut.new_unit();
+            assert!(!ut.unioned(k1, k2));
+            ut.union(k1, k2);
+            assert!(ut.unioned(k1, k2));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct ByteKey(u32);
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct ByteValue(u8);
+
+impl UnifyKey for ByteKey {
+    type Value = ByteValue;
+    fn index(&self) -> u32 {
+        self.0
+    }
+    fn from_index(u: u32) -> ByteKey {
+        ByteKey(u)
+    }
+    fn tag() -> &'static str {
+        "ByteKey"
+    }
+}
+
+impl EqUnifyValue for ByteValue {}
+
+#[test]
+fn var_value_is_packed() {
+    // With a `u32` rank, a small value would be padded out to 12 bytes.
+    assert!(mem::size_of::<VarValue<ByteKey>>() < mem::size_of::<(ByteKey, ByteValue, u32)>());
+    assert_eq!(mem::size_of::<VarValue<ByteKey>>(), 8);
+
+    // With a unit value, the byte rank is padded out to the key's
+    // alignment, so the size is what it was with a `u32` rank.
+    assert_eq!(mem::size_of::<VarValue<UnitKey>>(), 8);
+}
+
+#[test]
+fn ordered_key_rank_saturates() {
+    all_modes! {
+        S for OrderedKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+
+            // Every union makes the new (rank 0) key the root, so the
+            // rank of the root grows by one each time.
+            let first = ut.new_key(OrderedRank(0));
+            for i in 1..300 {
+                let k = ut.new_key(OrderedRank(i));
+                ut.union(first, k);
+                assert_eq!(ut.find(first), k);
+            }
+        }
+    }
+}
+
+#[test]
+fn record_and_replay() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::with_recording();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(22));
+            let k3 = ut.new_key(Some(23));
+            ut.unify_var_var(k1, k2).unwrap();
+            assert!(ut.unify_var_var(k1, k3).is_err());
+            ut.unify_var_value(k3, None).unwrap();
+
+            let log = ut.take_log();
+            assert_eq!(log, vec![
+                Op::NewKey(None),
+                Op::NewKey(Some(22)),
+                Op::NewKey(Some(23)),
+                Op::UnifyVarVar(k1, k2),
+                Op::UnifyVarVar(k1, k3),
+                Op::UnifyVarValue(k3, None),
+            ]);
+            assert!(ut.take_log().is_empty());
+
+            let mut replayed: UnificationTable<S> = UnificationTable::replay(&log);
+            assert_eq!(replayed.len(), 3);
+            assert!(replayed.unioned(k1, k2));
+            assert!(!replayed.unioned(k1, k3));
+            assert_eq!(replayed.probe_value(k1), Some(22));
+            assert_eq!(replayed.probe_value(k3), Some(23));
+        }
+    }
+}
+
+#[test]
+fn record_and_replay_new_key_with() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::with_recording();
+            let k1 = ut.new_key_with((), 1);
+            let k2 = ut.new_key(());
+            ut.union(k1, k2);
+
+            let log = ut.take_log();
+            assert_eq!(log, vec![
+                Op::NewKeyWithRank((), 1),
+                Op::NewKey(()),
+                Op::UnifyVarVar(k1, k2),
+            ]);
+
+            let mut replayed: UnificationTable<S> = UnificationTable::replay(&log);
+            assert_eq!(replayed.len(), 2);
+            assert!(replayed.unioned(k1, k2));
+            // The ranked key stays the root, as in the original table.
+            assert_eq!(replayed.find(k2), k1);
+            assert_eq!(ut.find(k2), k1);
+        }
+    }
+}
+
+#[test]
+fn record_and_replay_unify_var_var_or() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::with_recording();
+            let k1 = ut.new_key(Some(1));
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(Some(3));
+            ut.unify_var_var_or(k1, k2, |_| panic!("no conflict"));
+            ut.unify_var_var_or(k2, k3, |_| Some(0));
+
+            let log = ut.take_log();
+            assert_eq!(&log[3..], &[
+                Op::UnifyVarVar(k1, k2),
+                Op::SetValue(k2, Some(0)),
+                Op::SetValue(k3, Some(0)),
+                Op::UnifyVarVar(k2, k3),
+            ]);
+
+            let mut replayed: UnificationTable<S> = UnificationTable::replay(&log);
+            assert!(replayed.unioned(k1, k3));
+            assert_eq!(replayed.probe_value(k1), Some(0));
+            assert_eq!(replayed.find(k1), ut.find(k1));
+        }
+    }
+}
+
+#[test]
+fn record_and_replay_unify_var_var_collecting() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::with_recording();
+            let k1 = ut.new_key(Some(1));
+            let k2 = ut.new_key(Some(2));
+            let k3 = ut.new_key(None);
+            let mut conflicts = vec![];
+            ut.unify_var_var_collecting(k1, k2, &mut conflicts);
+            ut.unify_var_var_collecting(k3, k1, &mut conflicts);
+            assert_eq!(conflicts.len(), 1);
+
+            let log = ut.take_log();
+            assert_eq!(log.len(), 7);
+            let mut replayed: UnificationTable<S> = UnificationTable::replay(&log);
+            assert!(replayed.unioned(k1, k2));
+            assert!(replayed.unioned(k1, k3));
+            assert_eq!(replayed.probe_value(k3), Some(1));
+        }
+    }
+}
+
+#[test]
+fn record_and_replay_unify_var_value_or() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::with_recording();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(2));
+            ut.unify_var_value_or(k1, Some(1), |_| panic!("no conflict"));
+            ut.unify_var_value_or(k2, Some(3), |_| Some(0));
+
+            let log = ut.take_log();
+            assert_eq!(&log[2..], &[
+                Op::UnifyVarValue(k1, Some(1)),
+                Op::SetValue(k2, Some(0)),
+            ]);
+
+            let mut replayed: UnificationTable<S> = UnificationTable::replay(&log);
+            assert_eq!(replayed.probe_value(k1), Some(1));
+            assert_eq!(replayed.probe_value(k2), Some(0));
+        }
+    }
+}
+
+#[test]
+fn no_recording_by_default() {
+    let mut ut: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    let k2 = ut.new_key(());
+    ut.union(k1, k2);
+    assert!(ut.take_log().is_empty());
+}
+
+#[test]
+fn new_key_with_rank() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key_with((), 3);
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            ut.union(k3, k2);
+
+            // Both `k2` and `k3` have lower rank, so `k1` stays the root.
+            ut.union(k2, k1);
+            assert_eq!(ut.find(k3), k1);
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+fn new_key_with_rank_out_of_range() {
+    let mut ut: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    ut.new_key_with((), 256);
+}
+
+#[test]
+fn unify_while() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<_> = (0..5).map(|_| ut.new_key(None)).collect();
+            let mut pairs = vec![(0, 1), (1, 2), (0, 2), (3, 4)].into_iter();
+            let merges = ut.unify_while(|| pairs.next().map(|(a, b)| (keys[a], keys[b])));
+            assert_eq!(merges, Ok(3));
+            assert!(ut.unioned(keys[0], keys[2]));
+            assert!(!ut.unioned(keys[0], keys[3]));
+
+            ut.unify_var_value(keys[0], Some(22)).unwrap();
+            ut.unify_var_value(keys[3], Some(23)).unwrap();
+            let mut pairs = vec![(0, 3), (2, 4)].into_iter();
+            let merges = ut.unify_while(|| pairs.next().map(|(a, b)| (keys[a], keys[b])));
+            assert_eq!(merges, Err(Mismatch { expected: 22, found: 23 }));
+            assert!(!ut.unioned(keys[0], keys[3]));
+        }
+    }
+}
+
+#[test]
+fn any_unioned() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            assert_eq!(ut.any_unioned(&[k0, k1, k2, k3]), None);
+            assert_eq!(ut.any_unioned(&[]), None);
+
+            ut.union(k1, k3);
+            ut.union(k0, k2);
+            assert_eq!(ut.any_unioned(&[k3, k0, k1, k2]), Some((k3, k1)));
+            assert_eq!(ut.any_unioned(&[k2, k1, k0]), Some((k2, k0)));
+            assert_eq!(ut.any_unioned(&[k0, k1]), None);
+        }
+    }
+}
+
+#[test]
+fn rollback_restores_updated_values() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(22));
+            let k3 = ut.new_key(None);
+            ut.unify_var_var(k1, k3).unwrap();
+
+            let snapshot = ut.snapshot();
+            ut.unify_var_value(k1, Some(22)).unwrap();
+            ut.unify_var_var(k3, k2).unwrap();
+            assert_eq!(ut.probe_value(k3), Some(22));
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.probe_value(k1), None);
+            assert_eq!(ut.probe_value(k2), Some(22));
+            assert!(ut.unioned(k1, k3));
+            assert!(!ut.unioned(k1, k2));
+        }
+    }
+}
+
+#[test]
+fn mismatch_error() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(Some(22));
+            let k2 = ut.new_key(Some(23));
+            let err = ut.unify_var_var(k1, k2).unwrap_err();
+            assert_eq!(err, Mismatch { expected: 22, found: 23 });
+            assert_eq!(err.to_string(), "expected `22`, found `23`");
+
+            let err = ut.unify_var_value(k2, Some(24)).unwrap_err();
+            assert_eq!(err, Mismatch { expected: 23, found: 24 });
+        }
+    }
+}
+
+#[test]
+fn unify_var_with_value_of() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(22));
+            let k3 = ut.new_key(Some(23));
+            ut.unify_var_with_value_of(k1, k2).unwrap();
+            assert_eq!(ut.probe_value(k1), Some(22));
+            assert!(!ut.unioned(k1, k2));
+
+            assert!(ut.unify_var_with_value_of(k3, k2).is_err());
+            assert_eq!(ut.probe_value(k3), Some(23));
+        }
+    }
+}
+
+#[test]
+fn len_and_is_empty() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            assert!(ut.is_empty());
+            assert_eq!(ut.len(), 0);
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            ut.union(k1, k2);
+            assert!(!ut.is_empty());
+            assert_eq!(ut.len(), 2);
+        }
+    }
+}
+
+#[test]
+fn prefer_root() {
+    all_modes! {
+        S for UnitKey => {
+            // By default, the second root wins on equal rank.
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            ut.union(k1, k2);
+            assert_eq!(ut.find(k1), k2);
+
+            let mut ut = UnificationTable::<S>::new().prefer(|a, b| if a.0 < b.0 { a } else { b });
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            ut.union(k2, k1);
+            assert_eq!(ut.find(k2), k1);
+            ut.union(k1, k2);
+            assert_eq!(ut.find(k2), k1);
+
+            // Rank still takes priority over the preference.
+            ut.union(k3, k2);
+            assert_eq!(ut.find(k3), k1);
+        }
+    }
+}
+
+#[test]
+fn prefer_root_capturing() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    all_modes! {
+        S for UnitKey => {
+            // Prefer whichever root is listed first in `order`.
+            let order = [2, 0, 1, 3];
+            let mut ut = UnificationTable::<S>::new().prefer(move |a: UnitKey, b: UnitKey| {
+                let pos = |k: UnitKey| order.iter().position(|&i| i == k.0).unwrap();
+                if pos(a) < pos(b) { a } else { b }
+            });
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+
+            // Clones keep the policy.
+            let mut copy = ut.clone();
+            ut.union(k0, k2);
+            assert_eq!(ut.find(k0), k2);
+            copy.union(k1, k3);
+            assert_eq!(copy.find(k3), k1);
+        }
+    }
+
+    // The policy does not stop the table from being sent.
+    let ut = UnificationTable::<InPlace<UnitKey>>::new().prefer(|a, _| a);
+    assert_send_sync(&ut);
+}
+
+#[test]
+fn reset_values() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(Some(22));
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(Some(23));
+            ut.unify_var_var(k1, k2).unwrap();
+
+            let mut calls = 0;
+            ut.reset_values(|key| {
+                calls += 1;
+                Some(key.0 as i32 * 10)
+            });
+            assert_eq!(calls, 2);
+
+            assert!(ut.unioned(k1, k2));
+            let root = ut.find(k1);
+            assert_eq!(ut.probe_value(k1), Some(root.0 as i32 * 10));
+            assert_eq!(ut.probe_value(k2), Some(root.0 as i32 * 10));
+            assert_eq!(ut.probe_value(k3), Some(20));
+        }
+    }
+}
+
+#[test]
+fn find_stats() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut = UnificationTable::<S>::new().with_stats();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            assert_eq!(ut.find_stats(), FindStats::default());
+
+            ut.union(k0, k1); // 2 lookups, 0 steps; k0 -> k1
+            ut.union(k2, k3); // 2 lookups, 0 steps; k2 -> k3
+            ut.union(k1, k3); // 2 lookups, 0 steps; k1 -> k3
+            assert_eq!(ut.find(k0), k3); // 2 steps, compresses k0 -> k3
+            assert_eq!(ut.find(k0), k3); // 1 step
+            assert_eq!(ut.find_stats(), FindStats { calls: 8, total_steps: 3, max_steps: 2 });
+
+            ut.reset_stats();
+            assert_eq!(ut.find(k3), k3);
+            assert_eq!(ut.find_stats(), FindStats { calls: 1, total_steps: 0, max_steps: 0 });
+        }
+    }
+}
+
+#[test]
+fn no_stats_by_default() {
+    let mut ut: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    ut.find(k1);
+    assert_eq!(ut.find_stats(), FindStats::default());
+}
+
+#[test]
+fn tagged_errors() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(Some(22));
+            let k2 = ut.new_key(Some(23));
+            let err = ut.unify_var_var_tagged(k1, k2).unwrap_err();
+            assert_eq!(err, TaggedError { tag: "IntKey", error: Mismatch { expected: 22, found: 23 } });
+            assert_eq!(err.to_string(), "IntKey unification failed: expected `22`, found `23`");
+
+            let err = ut.unify_var_value_tagged(k1, Some(24)).unwrap_err();
+            assert_eq!(err.tag, "IntKey");
+            assert!(ut.unify_var_value_tagged(k1, Some(22)).is_ok());
+        }
+    }
+}
+
+#[test]
+fn class_size() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            assert_eq!(ut.class_size(k0), 1);
+            ut.union(k0, k1);
+            ut.union(k1, k3);
+            assert_eq!(ut.class_size(k0), 3);
+            assert_eq!(ut.class_size(k3), 3);
+            assert_eq!(ut.class_size(k2), 1);
+        }
+    }
+}
+
+#[test]
+fn in_snapshot() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(22));
+            let k3 = ut.new_key(Some(23));
+
+            let result = ut.in_snapshot(|ut| {
+                ut.unify_var_var(k1, k2)?;
+                ut.unify_var_var(k1, k3)
+            });
+            assert!(result.is_err());
+            assert!(!ut.unioned(k1, k2));
+            assert_eq!(ut.probe_value(k1), None);
+
+            let result: Result<_, Mismatch<i32>> = ut.in_snapshot(|ut| {
+                ut.unify_var_var(k1, k2)?;
+                // A failing inner call only undoes its own changes.
+                assert!(ut.in_snapshot(|ut| ut.unify_var_var(k1, k3)).is_err());
+                Ok(ut.new_key(None))
+            });
+            let k4 = result.unwrap();
+            assert!(ut.unioned(k1, k2));
+            assert!(!ut.unioned(k1, k3));
+            assert_eq!(ut.len(), 4);
+            assert_eq!(ut.probe_value(k4), None);
+        }
+    }
+}
+
+#[test]
+fn is_root() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            assert!(ut.is_root(k1));
+            assert!(ut.is_root(k2));
+
+            ut.union(k1, k2);
+            let root = ut.find(k1);
+            let other = if root == k1 { k2 } else { k1 };
+            assert!(ut.is_root(root));
+            assert!(!ut.is_root(other));
+        }
+    }
+}
+
+#[test]
+fn from_partition() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> =
+                UnificationTable::from_partition(&[&[None, Some(3)], &[None], &[None, None, None]])
+                    .unwrap();
+            let k = |i| IntKey::from_index(i);
+            assert_eq!(ut.len(), 6);
+            assert!(ut.unioned(k(0), k(1)));
+            assert!(!ut.unioned(k(1), k(2)));
+            assert!(!ut.unioned(k(2), k(3)));
+            assert!(ut.unioned(k(3), k(5)));
+            assert_eq!(ut.probe_value(k(0)), Some(3));
+            assert_eq!(ut.probe_value(k(4)), None);
+
+            let result: Result<UnificationTable<S>, _> =
+                UnificationTable::from_partition(&[&[Some(1), Some(2)]]);
+            assert!(result.is_err());
+        }
+    }
+}
+
+#[test]
+fn compact() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(None);
+            let k1 = ut.new_key(Some(1));
+            ut.new_key(None);
+            let k3 = ut.new_key(None);
+            let k4 = ut.new_key(None);
+            ut.unify_var_var(k0, k1).unwrap();
+            ut.unify_var_var(k3, k4).unwrap();
+            let dead_root = ut.find(k3);
+            let live_member = if dead_root == k3 { k4 } else { k3 };
+
+            let mut live = BitVector::new(ut.len());
+            for &k in &[k0, k1, live_member] {
+                live.insert(k.index() as usize);
+            }
+            let map = ut.compact(&live);
+
+            assert_eq!(ut.len(), 2);
+            assert_eq!(map, vec![Some(IntKey::from_index(0)), Some(IntKey::from_index(1)), None, None, None]);
+            assert!(ut.unioned(IntKey::from_index(0), IntKey::from_index(1)));
+            assert_eq!(ut.probe_value(IntKey::from_index(0)), Some(1));
+        }
+    }
+}
+
+#[test]
+fn compact_in_snapshot() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            ut.new_key(());
+            let snapshot = ut.snapshot();
+            ut.new_key(());
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                ut.compact(&BitVector::new(2));
+            }));
+            let message = result.unwrap_err().downcast::<&str>().unwrap();
+            assert!(message.contains("snapshot is open"));
+            ut.rollback_to(snapshot);
+            assert_eq!(ut.len(), 1);
+
+            // With the snapshot closed, compacting is allowed again.
+            ut.compact(&BitVector::new(1));
+            assert_eq!(ut.len(), 0);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "cannot compact a unification table while a snapshot is open")]
+fn retain_classes_in_snapshot() {
+    let mut ut: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    ut.new_key(());
+    let _snapshot = ut.snapshot();
+    ut.retain_classes(|_, _| true);
+}
+
+#[test]
+fn probe_or_set() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(Some(7));
+            ut.unify_var_var(k1, k2).unwrap();
+
+            assert_eq!(ut.probe_or_set(k1, || Some(3)), Some(3));
+            assert_eq!(ut.probe_value(k2), Some(3));
+            assert_eq!(ut.probe_or_set(k2, || panic!("already set")), Some(3));
+            assert_eq!(ut.probe_or_set(k3, || Some(4)), Some(7));
+            assert!(ut.unify_var_var(k1, k3).is_err());
+        }
+    }
+}
+
+#[cfg(feature = "petgraph")]
+#[test]
+fn to_forest_graph() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            ut.new_key(());
+            ut.union(k1, k2);
+
+            let graph = ut.to_forest_graph();
+            assert_eq!(graph.node_count(), 3);
+            assert_eq!(graph.edge_count(), 1);
+            let edge = &graph.raw_edges()[0];
+            let root = ut.find(k1);
+            assert_eq!(graph[edge.target()], root);
+            assert_ne!(graph[edge.source()], root);
+        }
+    }
+}
+
+#[test]
+fn unify_var_var_collecting() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(Some(1));
+            let k2 = ut.new_key(Some(2));
+            let k3 = ut.new_key(None);
+            let k4 = ut.new_key(Some(4));
+
+            let mut conflicts = vec![];
+            ut.unify_var_var_collecting(k1, k2, &mut conflicts);
+            ut.unify_var_var_collecting(k3, k1, &mut conflicts);
+            ut.unify_var_var_collecting(k4, k3, &mut conflicts);
+
+            assert!(ut.unioned(k1, k2));
+            assert!(ut.unioned(k4, k2));
+            assert_eq!(ut.probe_value(k3), Some(4));
+            assert_eq!(
+                conflicts,
+                vec![
+                    Conflict { a: k1, b: k2, error: Mismatch { expected: 1, found: 2 } },
+                    Conflict { a: k4, b: k3, error: Mismatch { expected: 4, found: 1 } },
+                ]
+            );
+        }
+    }
+}
+
+#[test]
+fn values_since_snapshot_iter() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+
+            let snapshot = ut.snapshot();
+            // Updates to existing keys pad the undo log, but are not
+            // reported.
+            ut.union(k0, k1);
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            ut.union(k2, k0);
+
+            let keys: Vec<_> = ut.values_since_snapshot_iter(&snapshot).map(|(k, _)| k).collect();
+            assert_eq!(keys, vec![k2, k3]);
+            let parents: Vec<_> = ut.values_since_snapshot_iter(&snapshot)
+                .map(|(k, value)| value.parent(k).is_some())
+                .collect();
+            assert_eq!(parents, vec![true, false]);
+            ut.rollback_to(snapshot);
+        }
+    }
+}
+
+unify_key! {
+    /// A key defined through the `unify_key!` macro.
+    struct MacroKey(u32) => Option<i32>; "MacroKey"
+}
+
+#[test]
+fn unify_key_macro() {
+    assert_eq!(MacroKey::tag(), "MacroKey");
+    assert_eq!(MacroKey::from_index(3).index(), 3);
+
+    all_modes! {
+        S for MacroKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(5));
+            ut.unify_var_var(k1, k2).unwrap();
+            assert_eq!(ut.probe_value(k1), Some(5));
+        }
+    }
+}
+
+#[test]
+fn unset_value() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(Some(4));
+            ut.unify_var_var(k1, k2).unwrap();
+            ut.unify_var_value(k1, Some(3)).unwrap();
+
+            ut.unset_value(k2);
+            assert!(ut.unioned(k1, k2));
+            assert_eq!(ut.probe_value(k1), None);
+
+            // With the value retracted, merging with another set
+            // value no longer conflicts.
+            ut.unify_var_var(k1, k3).unwrap();
+            assert_eq!(ut.probe_value(k2), Some(4));
+        }
+    }
+}
+
+#[test]
+fn replay_set_value() {
+    let mut ut: UnificationTable<InPlace<IntKey>> = UnificationTable::with_recording();
+    let k1 = ut.new_key(Some(1));
+    let k2 = ut.new_key(None);
+    ut.unset_value(k1);
+    ut.probe_or_set(k2, || Some(2));
+    let log = ut.take_log();
+    assert_eq!(log[2], Op::SetValue(k1, None));
+    assert_eq!(log[3], Op::SetValue(k2, Some(2)));
+
+    let mut replayed: UnificationTable<InPlace<IntKey>> = UnificationTable::replay(&log);
+    assert_eq!(replayed.probe_value(k1), None);
+    assert_eq!(replayed.probe_value(k2), Some(2));
+}
+
+#[test]
+fn read_snapshot() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(2));
+            let k3 = ut.new_key(None);
+            ut.unify_var_var(k1, k2).unwrap();
+
+            let view = ut.read_snapshot();
+            ut.unify_var_var(k1, k3).unwrap();
+            ut.new_key(None);
+
+            assert_eq!(view.len(), 3);
+            assert!(view.unioned(k1, k2));
+            assert!(!view.unioned(k1, k3));
+            assert_eq!(view.find(k2), view.find(k1));
+            assert_eq!(*view.probe_value(k1), Some(2));
+            assert_eq!(*view.probe_value(k3), None);
+            assert_eq!(ut.probe_value(k3), Some(2));
+        }
+    }
+}
+
+#[test]
+fn extend_with_pairs() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<_> = (0..5).map(|_| ut.new_key(())).collect();
+            ut.extend(vec![(keys[0], keys[1]), (keys[3], keys[1])]);
+            assert!(ut.unioned(keys[0], keys[3]));
+            assert!(!ut.unioned(keys[0], keys[2]));
+            assert!(!ut.unioned(keys[2], keys[4]));
+        }
+    }
+}
+
+#[test]
+fn class_size_histogram() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            assert!(ut.class_size_histogram().is_empty());
+
+            let keys: Vec<_> = (0..8).map(|_| ut.new_key(())).collect();
+            ut.union(keys[0], keys[1]);
+            ut.union(keys[1], keys[2]);
+            ut.union(keys[3], keys[4]);
+            ut.union(keys[5], keys[6]);
+
+            let histogram = ut.class_size_histogram();
+            assert_eq!(histogram.into_iter().collect::<Vec<_>>(), vec![(1, 1), (2, 2), (3, 1)]);
+        }
+    }
+}
+
+#[test]
+fn unify_var_value_checked() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            ut.unify_var_var(k1, k2).unwrap();
+
+            assert_eq!(ut.unify_var_value_checked(k1, None), Ok(false));
+            assert_eq!(ut.unify_var_value_checked(k1, Some(3)), Ok(true));
+            assert_eq!(ut.unify_var_value_checked(k2, Some(3)), Ok(false));
+            assert_eq!(
+                ut.unify_var_value_checked(k2, Some(4)),
+                Err(UnifyError { key: k2, error: Mismatch { expected: 3, found: 4 } })
+            );
+            assert_eq!(ut.probe_value(k1), Some(3));
+        }
+    }
+}
+
+#[test]
+fn validate_after_snapshots() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<_> = (0..8).map(|_| ut.new_key(())).collect();
+            ut.union(keys[0], keys[1]);
+
+            let outer = ut.snapshot();
+            ut.union(keys[2], keys[3]);
+            let inner = ut.snapshot();
+            ut.union(keys[1], keys[3]);
+            ut.new_key(());
+            assert_eq!(ut.validate(), Ok(()));
+            ut.rollback_to(inner);
+            assert_eq!(ut.validate(), Ok(()));
+            let inner = ut.snapshot();
+            ut.union(keys[4], keys[5]);
+            ut.union(keys[5], keys[0]);
+            ut.commit(inner);
+            ut.find(keys[4]);
+            assert_eq!(ut.validate(), Ok(()));
+            ut.rollback_to(outer);
+            assert_eq!(ut.validate(), Ok(()));
+            assert_eq!(ut.len(), 8);
+
+            // Corrupt the table behind its back.
+            ut.values.update(6, |value| value.parent = UnitKey(7));
+            ut.values.update(7, |value| value.parent = UnitKey(6));
+            assert!(ut.validate().unwrap_err().contains("cycle"));
+            ut.values.update(7, |value| value.parent = UnitKey(20));
+            assert!(ut.validate().unwrap_err().contains("parent 20"));
+        }
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "snapshot used out of order")]
+fn out_of_order_rollback() {
+    let mut ut: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    let outer = ut.snapshot();
+    ut.new_key(());
+    let inner = ut.snapshot();
+    ut.new_key(());
+    ut.commit(outer);
+    ut.rollback_to(inner);
+}
+
+#[test]
+#[cfg(all(feature = "persistent", debug_assertions))]
+#[should_panic(expected = "snapshot used out of order")]
+fn persistent_out_of_order_rollback() {
+    let mut ut: UnificationTable<Persistent<UnitKey>> = UnificationTable::new();
+    let outer = ut.snapshot();
+    ut.new_key(());
+    let inner = ut.snapshot();
+    ut.new_key(());
+    ut.commit(outer);
+    ut.rollback_to(inner);
+}
+
+#[test]
+fn map_values_into() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(2));
+            ut.new_key(None);
+            ut.unify_var_var(k1, k2).unwrap();
+
+            let mut mapped = ut.map_values_into::<UnitKey>(|_| ());
+            assert_eq!(mapped.raw_parents(), ut.raw_parents());
+            assert!(mapped.unioned(UnitKey(0), UnitKey(1)));
+            assert!(!mapped.unioned(UnitKey(0), UnitKey(2)));
+
+            let mut known = ut.map_values_into::<ByteKey>(|value| ByteValue(value.is_some() as u8));
+            assert_eq!(known.probe_value(ByteKey(0)), ByteValue(1));
+            assert_eq!(known.probe_value(ByteKey(2)), ByteValue(0));
+        }
+    }
+}
+
+#[test]
+fn entry() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(Some(4));
+
+            {
+                let mut entry = ut.entry(k1);
+                assert_eq!(entry.key(), k1);
+                assert_eq!(entry.value(), None);
+                entry.union_with(k2).unwrap().set(Some(4)).unwrap().union_with(k3).unwrap();
+                assert_eq!(entry.value(), Some(4));
+                assert!(entry.set(Some(5)).is_err());
+                assert_eq!(entry.find(), ut.find(k3));
+            }
+            assert!(ut.unioned(k2, k3));
+            assert_eq!(ut.probe_value(k2), Some(4));
+        }
+    }
+}
+
+#[test]
+fn store_extend() {
+    all_modes! {
+        S for IntKey => {
+            let mut store = S::default();
+            store.push(VarValue::new_var(IntKey(0), None));
+            store.extend((1..4).map(|i| VarValue::new_var(IntKey(i), Some(i as i32))));
+            assert_eq!(store.len(), 4);
+            assert_eq!(store[3], VarValue::new_var(IntKey(3), Some(3)));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct RcKey(u32);
+
+impl UnifyKey for RcKey {
+    type Value = Option<Rc<String>>;
+    fn index(&self) -> u32 {
+        self.0
+    }
+    fn from_index(u: u32) -> RcKey {
+        RcKey(u)
+    }
+    fn tag() -> &'static str {
+        "RcKey"
+    }
+}
+
+impl EqUnifyValue for Rc<String> {}
+
+#[test]
+fn unify_var_value_interned() {
+    all_modes! {
+        S for RcKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(None);
+            let mut interner = Interner::new();
+
+            ut.unify_var_value_interned(k1, Some(Rc::new("int".to_string())), &mut interner).unwrap();
+            ut.unify_var_value_interned(k2, Some(Rc::new("int".to_string())), &mut interner).unwrap();
+            ut.unify_var_value_interned(k3, Some(Rc::new("bool".to_string())), &mut interner).unwrap();
+            assert_eq!(interner.len(), 2);
+
+            let v1 = ut.probe_value(k1).unwrap();
+            let v2 = ut.probe_value(k2).unwrap();
+            let v3 = ut.probe_value(k3).unwrap();
+            assert!(Rc::ptr_eq(&v1, &v2));
+            assert_eq!(*v3, "bool");
+            assert!(ut.unify_var_value_interned(k3, Some(v1), &mut interner).is_err());
+
+            // Clearing the interner leaves the stored values alone.
+            interner.clear();
+            ut.unify_var_value_interned(k2, Some(Rc::new("int".to_string())), &mut interner).unwrap();
+            assert!(Rc::ptr_eq(&ut.probe_value(k2).unwrap(), &v2));
+        }
+    }
+}
+
+#[test]
+fn save_and_restore_value() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            ut.unify_var_var(k1, k2).unwrap();
+            let root = ut.find(k1);
+
+            let saved = ut.save_value(root);
+            ut.unify_var_value(k2, Some(3)).unwrap();
+            assert_eq!(ut.probe_value(k1), Some(3));
+            ut.restore_value(root, saved);
+            assert_eq!(ut.probe_value(k1), None);
+            assert!(ut.unioned(k1, k2));
+        }
+    }
+}
+
+#[test]
+fn num_distinct_values() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            assert_eq!(ut.num_distinct_values(), 0);
+            let k1 = ut.new_key(Some(1));
+            let k2 = ut.new_key(None);
+            ut.new_key(Some(1));
+            let k4 = ut.new_key(Some(2));
+            let k5 = ut.new_key(None);
+            assert_eq!(ut.num_distinct_values(), 3);
+
+            ut.unify_var_var(k1, k2).unwrap();
+            assert_eq!(ut.num_distinct_values(), 3);
+            ut.unify_var_var(k4, k5).unwrap();
+            assert_eq!(ut.num_distinct_values(), 2);
+        }
+    }
+}
+
+#[test]
+fn on_growth() {
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(vec![]));
+    let mut ut: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    ut.new_key(());
+    let mut calls = 0;
+    let hook_seen = seen.clone();
+    ut.on_growth(3, move |len| {
+        calls += 1;
+        hook_seen.lock().unwrap().push((calls, len));
+    });
+    ut.new_key(());
+    assert!(seen.lock().unwrap().is_empty());
+    ut.new_key(());
+    assert_eq!(*seen.lock().unwrap(), [(1, 3)]);
+    for _ in 0..3 {
+        ut.new_key_with((), 0);
+    }
+    assert_eq!(*seen.lock().unwrap(), [(1, 3), (2, 6)]);
+
+    // A clone does not share (or inherit) the hook.
+    let mut clone = ut.clone();
+    for _ in 0..3 {
+        clone.new_key(());
+    }
+    assert_eq!(seen.lock().unwrap().len(), 2);
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct VetoKey(u32);
+
+/// A value that refuses to merge two classes that have both already
+/// been merged with something.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Veto;
+
+impl UnifyKey for VetoKey {
+    type Value = Option<Veto>;
+    fn index(&self) -> u32 {
+        self.0
+    }
+    fn from_index(u: u32) -> VetoKey {
+        VetoKey(u)
+    }
+    fn tag() -> &'static str {
+        "VetoKey"
+    }
+}
+
+impl UnifyValue for Veto {
+    type Error = UnifyCtx;
+
+    fn unify_values(_: &Veto, _: &Veto) -> Result<Veto, UnifyCtx> {
+        Ok(Veto)
+    }
+
+    fn unify_values_ctx(_: &Veto, _: &Veto, ctx: UnifyCtx) -> Result<Veto, UnifyCtx> {
+        if ctx.rank1 > 0 && ctx.rank2 > 0 {
+            Err(ctx)
+        } else {
+            Ok(Veto)
+        }
+    }
+}
+
+#[test]
+fn unify_values_ctx() {
+    all_modes! {
+        S for VetoKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<_> = (0..4).map(|_| ut.new_key(Some(Veto))).collect();
+            ut.unify_var_var(k[0], k[1]).unwrap();
+            ut.unify_var_var(k[2], k[3]).unwrap();
+            assert_eq!(ut.unify_var_var(k[0], k[2]), Err(UnifyCtx { rank1: 1, rank2: 1 }));
+            assert!(!ut.unioned(k[0], k[2]));
+
+            // The plain `unify_values` path is unaffected.
+            ut.unify_var_value(k[0], Some(Veto)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn find_without_compression() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<_> = (0..8).map(|_| ut.new_key(())).collect();
+            for pair in keys.chunks(2) {
+                ut.union(pair[0], pair[1]);
+            }
+            ut.union(keys[0], keys[2]);
+            ut.union(keys[4], keys[6]);
+            ut.union(keys[0], keys[4]);
+
+            let parents = ut.raw_parents();
+            let before: Vec<_> = keys.iter().map(|&k| ut.find_without_compression(k)).collect();
+            assert_eq!(ut.raw_parents(), parents);
+            let after: Vec<_> = keys.iter().map(|&k| ut.find(k)).collect();
+            assert_eq!(before, after);
+            assert_ne!(ut.raw_parents(), parents);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "cycle")]
+fn find_without_compression_cycle() {
+    let mut ut: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    ut.new_key(());
+    ut.new_key(());
+    ut.values.update(0, |value| value.parent = UnitKey(1));
+    ut.values.update(1, |value| value.parent = UnitKey(0));
+    ut.find_without_compression(UnitKey(0));
+}
+
+#[test]
+fn var_value_new_for_test() {
+    all_modes! {
+        S for UnitKey => {
+            // Key 1 is a rank-1 root with keys 0 and 2 pointing at it.
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            ut.values.push(VarValue::new_for_test(1, (), 0));
+            ut.values.push(VarValue::new_for_test(1, (), 1));
+            ut.values.push(VarValue::new_for_test(1, (), 0));
+            ut.values.push(VarValue::new_for_test(3, (), 300));
+            assert_eq!(ut.validate(), Ok(()));
+            assert_eq!(ut.raw_parents(), vec![1, 1, 1, 3]);
+            assert_eq!(ut.find(UnitKey(2)), UnitKey(1));
+            assert!(!ut.unioned(UnitKey(0), UnitKey(3)));
+
+            ut.values.push(VarValue::new_for_test(9, (), 0));
+            assert!(ut.validate().unwrap_err().contains("parent 9"));
+        }
+    }
+}
+
+#[test]
+fn partition_diff() {
+    all_modes! {
+        S for UnitKey => {
+            let mut a: UnificationTable<S> = UnificationTable::new();
+            let mut b: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<_> = (0..6).map(|_| { b.new_key(()); a.new_key(()) }).collect();
+            assert_eq!(a.partition_diff(&mut b), vec![]);
+
+            a.union(keys[0], keys[1]);
+            a.union(keys[1], keys[2]);
+            a.union(keys[3], keys[4]);
+            b.union(keys[3], keys[4]);
+            b.union(keys[4], keys[5]);
+
+            // Both non-root keys of {0, 1, 2} are missing from `b`.
+            let diff = a.partition_diff(&mut b);
+            assert_eq!(diff.len(), 2);
+            for &(root, key) in &diff {
+                assert_eq!(root, a.find(keys[0]));
+                assert!(key.index() < 3 && key != root);
+                assert!(!b.unioned(root, key));
+            }
+
+            let diff = b.partition_diff(&mut a);
+            assert_eq!(diff.len(), 1);
+            assert!(diff[0].0 == keys[5] || diff[0].1 == keys[5]);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "different numbers of keys")]
+fn partition_diff_length_mismatch() {
+    let mut a: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    let mut b: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    a.new_key(());
+    a.partition_diff(&mut b);
+}
+
+#[test]
+fn has_value() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(Some(0));
+            assert!(!ut.has_value(k1));
+            assert!(ut.has_value(k3));
+
+            ut.unify_var_var(k1, k2).unwrap();
+            ut.unify_var_value(k2, Some(5)).unwrap();
+            assert!(ut.has_value(k1));
+
+            ut.unset_value(k1);
+            assert!(!ut.has_value(k2));
+        }
+    }
+}
+
+#[test]
+fn bounds_check_policies() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new()
+                .with_bounds_check(OnOob::Error);
+            let k1 = ut.new_key(Some(1));
+            let k2 = ut.new_key(None);
+            let snapshot = ut.snapshot();
+            let stale = ut.new_key(Some(3));
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.try_find(k1), Ok(k1));
+            assert_eq!(ut.try_find(stale), Err(OutOfBounds(stale)));
+            assert_eq!(ut.try_probe_value(stale), Err(OutOfBounds(stale)));
+            assert!(ut.try_unify_var_var(k1, stale).is_err());
+            assert!(!ut.unioned(k1, k2));
+            assert_eq!(ut.try_unify_var_var(k1, k2), Ok(Ok(())));
+
+            let mut ut = ut.with_bounds_check(OnOob::Saturate);
+            let k3 = ut.new_key(Some(1));
+            assert_eq!(ut.try_find(IntKey(10)), Ok(k3));
+            assert_eq!(ut.try_probe_value(IntKey(10)), Ok(Some(1)));
+            assert_eq!(ut.try_unify_var_var(k1, IntKey(10)), Ok(Ok(())));
+            assert!(ut.unioned(k1, k3));
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "out of bounds")]
+fn bounds_check_panic() {
+    let mut ut: UnificationTable<InPlace<IntKey>> =
+        UnificationTable::new().with_bounds_check(OnOob::Panic);
+    ut.new_key(None);
+    let _ = ut.try_find(IntKey(1));
+}
+
+#[test]
+fn retain_classes() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<_> = (0..6).map(|_| ut.new_key(None)).collect();
+            ut.unify_var_var(keys[0], keys[3]).unwrap();
+            ut.unify_var_var(keys[1], keys[4]).unwrap();
+            ut.unify_var_value(keys[4], Some(7)).unwrap();
+            ut.unify_var_value(keys[5], Some(8)).unwrap();
+
+            let mut calls = 0;
+            let map = ut.retain_classes(|_, value| { calls += 1; *value != Some(7) });
+            assert_eq!(calls, 4);
+
+            let new = |k: IntKey| map[k.index() as usize];
+            assert_eq!(new(keys[1]), None);
+            assert_eq!(new(keys[4]), None);
+            assert_eq!(ut.len(), 4);
+            assert!(ut.unioned(new(keys[0]).unwrap(), new(keys[3]).unwrap()));
+            assert!(!ut.unioned(new(keys[0]).unwrap(), new(keys[2]).unwrap()));
+            assert_eq!(ut.probe_value(new(keys[5]).unwrap()), Some(8));
+        }
+    }
+}
+
+#[test]
+fn serialize_compact_round_trip() {
+    fn encode(value: &Option<i32>, out: &mut Vec<u8>) {
+        match *value {
+            None => out.push(0),
+            Some(n) => {
+                out.push(1);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+    }
+
+    fn decode(bytes: &mut &[u8]) -> Option<Option<i32>> {
+        let (&tag, rest) = bytes.split_first()?;
+        *bytes = rest;
+        match tag {
+            0 => Some(None),
+            1 if bytes.len() >= 4 => {
+                let (n, rest) = bytes.split_at(4);
+                *bytes = rest;
+                Some(Some(i32::from_le_bytes([n[0], n[1], n[2], n[3]])))
+            }
+            _ => None,
+        }
+    }
+
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<_> = (0..200).map(|_| ut.new_key(None)).collect();
+            for i in 0..190 {
+                ut.unify_var_var(keys[i], keys[(i * 7) % 190]).unwrap();
+            }
+            ut.unify_var_value(keys[0], Some(-5)).unwrap();
+            ut.unify_var_value(keys[195], Some(-5)).unwrap();
+            ut.unify_var_value(keys[196], Some(9)).unwrap();
+
+            let mut bytes = vec![];
+            ut.serialize_compact(&mut bytes, encode);
+            // Most keys fit in a byte, and `Some(-5)` is written once.
+            assert!(bytes.len() < 2 * 200 + 20);
+
+            let mut copy: UnificationTable<S> =
+                UnificationTable::deserialize_compact(&bytes, decode).unwrap();
+            assert_eq!(copy.len(), ut.len());
+            assert_eq!(copy.validate(), Ok(()));
+            for &a in &keys {
+                assert_eq!(copy.probe_value(a), ut.probe_value(a));
+                for &b in &keys[190..] {
+                    assert_eq!(copy.unioned(a, b), ut.unioned(a, b));
+                }
+            }
+            assert_eq!(copy.partition_diff(&mut ut), vec![]);
+            assert_eq!(ut.partition_diff(&mut copy), vec![]);
+
+            let truncated = &bytes[..bytes.len() - 1];
+            assert!(UnificationTable::<S>::deserialize_compact(truncated, decode).is_none());
+            bytes.push(0);
+            assert!(UnificationTable::<S>::deserialize_compact(&bytes, decode).is_none());
+        }
+    }
+}
+
+#[test]
+fn deserialize_compact_too_many_values() {
+    all_modes! {
+        S for UnitKey => {
+            // One key in one class, but claiming 2^63 - 1 values. A decoder
+            // that reads nothing must not be called that many times.
+            let mut bytes = vec![1, 0, 1, 0];
+            bytes.extend_from_slice(&[0xff; 8]);
+            bytes.extend_from_slice(&[0xff, 0x00]);
+            let mut calls = 0;
+            let table = UnificationTable::<S>::deserialize_compact(&bytes, |_| {
+                calls += 1;
+                Some(())
+            });
+            assert!(table.is_none());
+            assert_eq!(calls, 0);
+
+            // A count that matches the classes is fine.
+            let table = UnificationTable::<S>::deserialize_compact(&[1, 0, 1, 0, 1], |_| Some(()));
+            assert_eq!(table.unwrap().len(), 1);
+        }
+    }
+}